@@ -0,0 +1,35 @@
+use clap::Args;
+use regex::Regex;
+
+/// Image reading and noise map options shared by the commands that measure
+/// the background and RMS of images
+#[derive(Args, Debug)]
+pub(crate) struct BackgroundArgs {
+    /// Pattern for the obsid in image file names, used when the OBSID,
+    /// GPSTIME and DATE-OBS headers are all missing
+    #[arg(long, default_value = r"(\d{10})")]
+    pub(super) obsid_regex: Regex,
+
+    /// Pixels between the points where the background and RMS are measured
+    #[arg(short, long, default_value_t = 20)]
+    pub(super) grid: usize,
+
+    /// Width in pixels of the box of pixels used at each grid point
+    #[arg(short, long, default_value_t = 100)]
+    pub(super) box_size: usize,
+}
+
+/// Source finding options shared by the commands that find islands
+#[derive(Args, Debug)]
+pub(crate) struct IslandArgs {
+    #[command(flatten)]
+    pub(super) background: BackgroundArgs,
+
+    /// Signal to noise an island needs to reach somewhere to be found
+    #[arg(short, long, default_value_t = 5.0)]
+    pub(super) seed: f64,
+
+    /// Signal to noise that pixels need to be part of an island
+    #[arg(long, default_value_t = 4.0)]
+    pub(super) flood: f64,
+}
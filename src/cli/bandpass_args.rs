@@ -0,0 +1,13 @@
+use clap::Args;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+#[clap(arg_required_else_help = true)]
+pub(crate) struct BandpassArgs {
+    #[arg(short, long, num_args=1..,)]
+    pub(super) files: Vec<PathBuf>,
+
+    /// Path of the solutions file to write
+    #[arg(short, long, default_value = "median_bandpass.fits")]
+    pub(super) output: PathBuf,
+}
@@ -1,3 +1,4 @@
+use crate::metrics::grouping::TileGrouping;
 use clap::Args;
 use std::path::PathBuf;
 
@@ -6,4 +7,34 @@ use std::path::PathBuf;
 pub(crate) struct CalArgs {
     #[arg(short, long, num_args=1..,)]
     pub(super) files: Vec<PathBuf>,
+
+    /// Metafits files, matched to the solutions by obsid. A single metafits
+    /// is used for every solutions file.
+    #[arg(short, long, num_args=1..,)]
+    pub(super) metafits: Vec<PathBuf>,
+
+    /// Normalise gain smoothness by the median bandpass of these solutions
+    /// instead of the median over tiles in each file
+    #[arg(short, long, num_args=1..,)]
+    pub(super) reference_bandpass: Vec<PathBuf>,
+
+    /// Also write per-tile metrics aggregated over these tile groups
+    #[arg(short, long, num_args=1.., value_enum, requires = "metafits")]
+    pub(super) group_by: Vec<TileGrouping>,
+
+    /// Flag tiles whose normalised XX/YY amplitude ratio deviates from 1 by
+    /// more than this
+    #[arg(long, default_value_t = 0.05)]
+    pub(super) ratio_threshold: f64,
+
+    /// Report gain steps at coarse channel boundaries more significant than
+    /// this many standard errors
+    #[arg(long, default_value_t = 5.0)]
+    pub(super) jump_threshold: f64,
+
+    /// History of metric values from previous runs. Each metric is also
+    /// written as z-scores against the history, then the new values are
+    /// added to it. The file is created if it doesn't exist.
+    #[arg(long)]
+    pub(super) history: Option<PathBuf>,
 }
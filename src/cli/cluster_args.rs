@@ -0,0 +1,18 @@
+use clap::Args;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+#[clap(arg_required_else_help = true)]
+pub(crate) struct ClusterArgs {
+    #[arg(short, long, num_args=1..,)]
+    pub(super) files: Vec<PathBuf>,
+
+    /// Number of clusters to split the tiles of each observation into
+    #[arg(
+        short = 'k',
+        long,
+        default_value_t = 3,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub(super) num_clusters: usize,
+}
@@ -0,0 +1,50 @@
+use crate::cli::background_args::IslandArgs;
+use clap::Args;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+#[clap(arg_required_else_help = true)]
+pub(crate) struct CrossmatchArgs {
+    #[arg(short, long, num_args=1..,)]
+    pub(super) files: Vec<PathBuf>,
+
+    /// Reference catalogue, either a FITS table like a GLEAM extract or a
+    /// CSV file with a header row
+    #[arg(short, long)]
+    pub(super) catalogue: PathBuf,
+
+    /// Catalogue column with the RA [deg]
+    #[arg(long, default_value = "ra")]
+    pub(super) ra_column: String,
+
+    /// Catalogue column with the Dec [deg]
+    #[arg(long, default_value = "dec")]
+    pub(super) dec_column: String,
+
+    /// Catalogue column with the flux density [Jy] at the reference frequency
+    #[arg(long, default_value = "flux")]
+    pub(super) flux_column: String,
+
+    /// Catalogue column with the spectral index, which can be missing
+    #[arg(long, default_value = "alpha")]
+    pub(super) alpha_column: String,
+
+    /// Frequency of the catalogue flux densities [Hz]
+    #[arg(long, default_value_t = 200e6)]
+    pub(super) ref_freq: f64,
+
+    /// Spectral index for sources without one in the catalogue
+    #[arg(long, default_value_t = -0.7, allow_negative_numbers = true)]
+    pub(super) alpha: f64,
+
+    /// Largest separation of a source from its reference match [arcsec]
+    #[arg(short = 'r', long, default_value_t = 60.0)]
+    pub(super) match_radius: f64,
+
+    #[command(flatten)]
+    pub(super) islands: IslandArgs,
+
+    /// Path of the per-image results
+    #[arg(short, long, default_value = "crossmatch.txt")]
+    pub(super) output: PathBuf,
+}
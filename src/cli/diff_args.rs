@@ -0,0 +1,20 @@
+use clap::Args;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+#[clap(arg_required_else_help = true)]
+pub(crate) struct DiffArgs {
+    /// Solutions to compare
+    pub(super) a: PathBuf,
+
+    /// Solutions to compare against, aligned to the tiles and channels of A
+    pub(super) b: PathBuf,
+
+    /// Path of the per-tile comparison table
+    #[arg(short, long, default_value = "solution_diff.txt")]
+    pub(super) output: PathBuf,
+
+    /// Also write the A/B gain ratio as a solutions file
+    #[arg(short, long)]
+    pub(super) ratio_solutions: Option<PathBuf>,
+}
@@ -1,4 +1,6 @@
+use crate::metrics::image::NoiseEstimator;
 use clap::Args;
+use regex::Regex;
 use std::path::PathBuf;
 
 #[derive(Args, Debug)]
@@ -6,4 +8,41 @@ use std::path::PathBuf;
 pub(crate) struct ImgArgs {
     #[arg(short, long, num_args=1..,)]
     pub(super) files: Vec<PathBuf>,
+
+    /// Pattern for the obsid in image file names, used when the OBSID,
+    /// GPSTIME and DATE-OBS headers are all missing. The first capture group,
+    /// or else the whole match, is the obsid.
+    #[arg(long, default_value = r"(\d{10})")]
+    pub(super) obsid_regex: Regex,
+
+    /// Noise estimators, each written as its own column along with the
+    /// dynamic range it gives. The RMS is always the first column.
+    #[arg(short, long, num_args=1.., value_enum, default_values_t = [NoiseEstimator::Rms])]
+    pub(super) noise: Vec<NoiseEstimator>,
+
+    /// Residual images, one per image in the same order. Without these, the
+    /// -residual.fits written by WSClean next to each -image.fits is used if
+    /// it exists.
+    #[arg(short, long, num_args=1..,)]
+    pub(super) residuals: Vec<PathBuf>,
+
+    /// Model images, one per image in the same order. Without these, the
+    /// -model.fits written by WSClean next to each -image.fits is used if it
+    /// exists.
+    #[arg(short, long, num_args=1..,)]
+    pub(super) models: Vec<PathBuf>,
+
+    /// Only use pixels within this many degrees of the phase centre for the
+    /// noise, which needs the images to have a celestial WCS
+    #[arg(long)]
+    pub(super) noise_radius: Option<f64>,
+
+    /// DS9 region file restricting every metric to pixels inside its regions
+    /// and outside its excluded (-) regions
+    #[arg(long)]
+    pub(super) regions: Option<PathBuf>,
+
+    /// FITS mask image restricting every metric to its non-zero pixels
+    #[arg(long)]
+    pub(super) mask: Option<PathBuf>,
 }
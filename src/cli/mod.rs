@@ -1,11 +1,41 @@
+mod background_args;
+mod bandpass_args;
 mod cal_args;
+mod cluster_args;
+mod crossmatch_args;
+mod diff_args;
 mod img_args;
-use crate::metrics::{gain_amplitude, gain_phase, image};
+mod noise_map_args;
+mod pca_args;
+mod smooth_args;
+mod source_args;
+mod stability_args;
+mod summary_args;
+use crate::metrics::grouping::{TileGrouping, group_tile_metric};
+use crate::metrics::history;
+use crate::metrics::{
+    background, bandpass, cluster, crossmatch, diff, flags, gain_amplitude, gain_phase, image,
+    jumps, pca, region, smooth, sources, stability, summary,
+};
+use crate::time::{self, OBSID_COLUMNS, obsid_columns};
 
-use crate::io::write::{write_results, write_results_1D};
+use crate::io::read::catalogue::{CatalogueColumns, CatalogueFile};
+use crate::io::read::history::HistoryFile;
+use crate::io::read::mask::MaskFile;
+use crate::io::read::metafits::{Metafits, MetafitsFile};
+use crate::io::read::region::RegionFile;
+use crate::io::read::results::ResultsFile;
+use crate::io::read::solutions::CalSolFile;
+use crate::io::write::{
+    replace_solutions, write_catalogue, write_group_results, write_history, write_image_planes,
+    write_jumps, write_labelled_rows, write_night_summary, write_ratio_flags, write_results,
+    write_results_1d, write_results_with_header, write_solutions, write_summary,
+    write_tile_ranking,
+};
 use clap::{Parser, Subcommand};
 use glob::glob;
 use itertools::Itertools;
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::path::{Path, PathBuf};
 
@@ -15,6 +45,10 @@ use std::path::{Path, PathBuf};
 pub(crate) struct Cli {
     #[clap(subcommand)]
     pub(crate) sub_command: Commands,
+
+    /// Array longitude [deg east] for the LST column of results files
+    #[arg(long, global = true, default_value_t = time::MWA_LONGITUDE, allow_negative_numbers = true)]
+    pub(crate) longitude: f64,
 }
 
 #[derive(Subcommand)]
@@ -31,45 +65,493 @@ pub(super) enum Commands {
 
     #[clap(about = "Calculate only EW and NS phase metrics")]
     PhaseMetrics(cal_args::CalArgs),
+
+    #[clap(about = "Rank tiles by how much their bandpass varies across observations")]
+    Stability(stability_args::StabilityArgs),
+
+    #[clap(about = "Write the per-tile median bandpass of many solutions as a solutions file")]
+    MakeBandpass(bandpass_args::BandpassArgs),
+
+    #[clap(about = "Fit smooth amplitude and phase models to solutions and write them out")]
+    SmoothSolutions(smooth_args::SmoothArgs),
+
+    #[clap(about = "Compare two sets of calibration solutions tile by tile")]
+    DiffSolutions(diff_args::DiffArgs),
+
+    #[clap(about = "Principal component analysis of tile bandpasses")]
+    Pca(pca_args::PcaArgs),
+
+    #[clap(about = "Cluster the tiles of each observation by bandpass shape")]
+    Cluster(cluster_args::ClusterArgs),
+
+    #[clap(about = "Summarise calibration metrics per observation and over the night")]
+    Summarize(summary_args::SummaryArgs),
+
+    #[clap(about = "Make background and RMS maps of images and summarise how the noise varies")]
+    NoiseMaps(noise_map_args::NoiseMapArgs),
+
+    #[clap(about = "Find sources in images and count real and negative detections")]
+    FindSources(source_args::SourceArgs),
+
+    #[clap(about = "Check the flux scale and astrometry of images against a reference catalogue")]
+    Crossmatch(crossmatch_args::CrossmatchArgs),
 }
 
 impl Commands {
-    pub(crate) fn run(&self) -> Result<(), Box<dyn Error>> {
+    pub(crate) fn run(&self, longitude: f64) -> Result<(), Box<dyn Error>> {
         match self {
-            Commands::ImgMetrics(args) => run_img_metrics(args),
-            Commands::CalMetrics(args) => run_cal_metrics(args),
-            Commands::AmpMetrics(args) => run_amp_metrics(args),
-            Commands::PhaseMetrics(args) => run_phase_metrics(args),
+            Commands::ImgMetrics(args) => run_img_metrics(args, longitude),
+            Commands::CalMetrics(args) => run_cal_metrics(args, longitude),
+            Commands::AmpMetrics(args) => run_amp_metrics(args, longitude),
+            Commands::PhaseMetrics(args) => run_phase_metrics(args, longitude),
+            Commands::Stability(args) => run_stability(args, longitude),
+            Commands::MakeBandpass(args) => run_make_bandpass(args),
+            Commands::SmoothSolutions(args) => run_smooth_solutions(args),
+            Commands::DiffSolutions(args) => run_diff_solutions(args),
+            Commands::Pca(args) => run_pca(args, longitude),
+            Commands::Cluster(args) => run_cluster(args, longitude),
+            Commands::Summarize(args) => run_summarize(args, longitude),
+            Commands::NoiseMaps(args) => run_noise_maps(args, longitude),
+            Commands::FindSources(args) => run_find_sources(args, longitude),
+            Commands::Crossmatch(args) => run_crossmatch(args, longitude),
         }
     }
 }
 
-fn run_img_metrics(args: &img_args::ImgArgs) -> Result<(), Box<dyn Error>> {
-    println!("Calculating image RMS and dynamic range");
+fn run_img_metrics(args: &img_args::ImgArgs, longitude: f64) -> Result<(), Box<dyn Error>> {
+    println!("Calculating image noise and dynamic range");
     let paths = resolve_paths(&args.files)?;
+    let (paths, sets) = image_sets(paths, &args.residuals, &args.models)?;
+    // image_rms.txt always starts with the RMS, then any other estimators,
+    // each only once however often they're requested
+    let mut estimators = vec![image::NoiseEstimator::Rms];
+    for &estimator in &args.noise {
+        if !estimators.contains(&estimator) {
+            estimators.push(estimator);
+        }
+    }
+    let selection = region::PixelSelection {
+        regions: match &args.regions {
+            Some(file_path) => RegionFile {
+                file_path: file_path.clone(),
+            }
+            .read()?,
+            None => vec![],
+        },
+        mask: args
+            .mask
+            .as_ref()
+            .map(|file_path| {
+                MaskFile {
+                    file_path: file_path.clone(),
+                }
+                .read()
+            })
+            .transpose()?,
+    };
 
-    let (obsids, rms_vec, dr_vec): (Vec<_>, Vec<_>, Vec<_>) = paths
+    let results: Vec<_> = paths
         .iter()
-        .map(|path| image::run_image_calc(path))
-        .filter_map(Result::ok)
+        .zip(&sets)
+        .filter_map(|(path, set)| {
+            image::run_image_calc(
+                path,
+                set,
+                &args.obsid_regex,
+                &estimators,
+                args.noise_radius,
+                &selection,
+            )
+            .inspect_err(|e| eprintln!("Warning: skipping {}: {}", path.display(), e))
+            .ok()
+        })
+        .collect();
+
+    // One row per plane
+    let (labels, noise_vec, dr_vec, peak_vec): (Vec<_>, Vec<_>, Vec<_>, Vec<_>) = results
+        .iter()
+        .flat_map(|(obsid, planes)| {
+            planes.iter().map(move |plane| {
+                (
+                    format!(
+                        "{} {} {} {} {} {}",
+                        obsid_columns(*obsid, longitude),
+                        plane.stokes,
+                        plane.freq,
+                        plane.num_valid_pixels,
+                        plane.num_noise_pixels,
+                        if plane.noise_from_residual {
+                            "residual"
+                        } else {
+                            "image"
+                        }
+                    ),
+                    plane.noise.clone(),
+                    plane.dr.clone(),
+                    vec![
+                        plane.peak,
+                        plane.peak_ra,
+                        plane.peak_dec,
+                        plane.residual_peak,
+                        plane.model_flux,
+                    ],
+                )
+            })
+        })
         .multiunzip();
 
-    write_results_1D(Path::new("image_rms.txt"), &obsids, &rms_vec)?;
-    write_results_1D(Path::new("image_dr.txt"), &obsids, &dr_vec)?;
+    let columns = format!(
+        "{} stokes freq[Hz] num_valid_pixels num_noise_pixels noise_from",
+        OBSID_COLUMNS
+    );
+    let names: Vec<&str> = estimators.iter().map(|n| n.name()).collect();
+    // Say which pixels were used at the top of each file
+    let mut notes: Vec<String> = [("regions in", &args.regions), ("mask", &args.mask)]
+        .iter()
+        .filter_map(|(what, path)| {
+            path.as_ref()
+                .map(|path| format!("Pixels restricted to {} {}", what, path.display()))
+        })
+        .collect();
+    let peak_header: Vec<String> = notes
+        .iter()
+        .cloned()
+        .chain([format!(
+            "{} peak peak_ra[deg] peak_dec[deg] max_abs_residual model_flux",
+            columns
+        )])
+        .collect();
+    if let Some(radius) = args.noise_radius {
+        notes.push(format!(
+            "Noise from pixels within {} deg of the phase centre",
+            radius
+        ));
+    }
+    let header = |lines: &[String]| -> Vec<String> { notes.iter().chain(lines).cloned().collect() };
+
+    let dr_names: Vec<String> = names.iter().map(|n| format!("dr_{}", n)).collect();
+    let noise_header = header(&[format!("{} {}", columns, names.join(" "))]);
+    let dr_header = header(&[
+        "Peak of the restored image divided by the noise of the residual image, or of".to_string(),
+        "the restored image itself if there is no residual".to_string(),
+        format!("{} {}", columns, dr_names.join(" ")),
+    ]);
+
+    write_labelled_rows(
+        Path::new("image_rms.txt"),
+        &noise_header.iter().map(String::as_str).collect::<Vec<_>>(),
+        &labels,
+        &noise_vec,
+    )?;
+    write_labelled_rows(
+        Path::new("image_dr.txt"),
+        &dr_header.iter().map(String::as_str).collect::<Vec<_>>(),
+        &labels,
+        &dr_vec,
+    )?;
+    write_labelled_rows(
+        Path::new("image_peaks.txt"),
+        &peak_header.iter().map(String::as_str).collect::<Vec<_>>(),
+        &labels,
+        &peak_vec,
+    )?;
+    Ok(())
+}
+
+/// Pair each image with its residual and model images, given explicitly or
+/// found next to it. Residual and model images that belong to another of the
+/// images are dropped from the list of images.
+fn image_sets(
+    paths: Vec<PathBuf>,
+    residuals: &[PathBuf],
+    models: &[PathBuf],
+) -> Result<(Vec<PathBuf>, Vec<image::ImageSet>), Box<dyn Error>> {
+    if residuals.is_empty() && models.is_empty() {
+        let paths: Vec<PathBuf> = paths
+            .iter()
+            .filter(|path| {
+                image::wsclean_restored_image(path)
+                    .is_none_or(|restored| !paths.contains(&restored))
+            })
+            .cloned()
+            .collect();
+        let sets = paths
+            .iter()
+            .map(|path| image::find_wsclean_set(path))
+            .collect();
+        return Ok((paths, sets));
+    }
+
+    let resolve = |files: &[PathBuf]| -> Result<Vec<Option<PathBuf>>, Box<dyn Error>> {
+        if files.is_empty() {
+            return Ok(vec![None; paths.len()]);
+        }
+        let resolved = resolve_paths(files)?;
+        if resolved.len() != paths.len() {
+            return Err(format!(
+                "Got {} images but {} residual or model images",
+                paths.len(),
+                resolved.len()
+            )
+            .into());
+        }
+        Ok(resolved.into_iter().map(Some).collect())
+    };
+
+    let sets = resolve(residuals)?
+        .into_iter()
+        .zip(resolve(models)?)
+        .map(|(residual, model)| image::ImageSet { residual, model })
+        .collect();
+    Ok((paths, sets))
+}
+
+fn run_noise_maps(
+    args: &noise_map_args::NoiseMapArgs,
+    longitude: f64,
+) -> Result<(), Box<dyn Error>> {
+    println!("Making background and RMS maps");
+    let paths = resolve_paths(&args.files)?;
+
+    let mut labels = vec![];
+    let mut rows = vec![];
+    for path in &paths {
+        let (image, maps, summaries) = match background::run_noise_map_calc(
+            path,
+            &args.background.obsid_regex,
+            args.background.grid,
+            args.background.box_size,
+        ) {
+            Ok(result) => result,
+            Err(e) => {
+                eprintln!("Warning: skipping {}: {}", path.display(), e);
+                continue;
+            }
+        };
+
+        let stem = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .expect("Unable to get file's stem");
+        write_image_planes(
+            &args.output_dir.join(format!("{}_bkg.fits", stem)),
+            &image,
+            &maps.background,
+        )?;
+        write_image_planes(
+            &args.output_dir.join(format!("{}_rms.fits", stem)),
+            &image,
+            &maps.rms,
+        )?;
+
+        for summary in summaries {
+            labels.push(format!(
+                "{} {} {}",
+                obsid_columns(image.id, longitude),
+                summary.stokes,
+                summary.freq
+            ));
+            rows.push(vec![
+                summary.median_background,
+                summary.median_rms,
+                summary.min_rms,
+                summary.max_rms,
+                summary.centre_rms,
+                summary.edge_rms,
+                summary.edge_to_centre(),
+            ]);
+        }
+    }
+
+    write_labelled_rows(
+        &args.output_dir.join("noise_maps.txt"),
+        &[
+            &format!(
+                "Centre and edge RMS are medians within {}% and beyond {}% of half the image",
+                background::CENTRE_FRACTION * 100.0,
+                background::EDGE_FRACTION * 100.0
+            ),
+            "size from its centre",
+            &format!(
+                "{} stokes freq[Hz] median_bkg median_rms min_rms max_rms centre_rms edge_rms edge_to_centre",
+                OBSID_COLUMNS
+            ),
+        ],
+        &labels,
+        &rows,
+    )?;
+    Ok(())
+}
+
+fn run_find_sources(args: &source_args::SourceArgs, longitude: f64) -> Result<(), Box<dyn Error>> {
+    println!("Finding sources");
+    let paths = resolve_paths(&args.files)?;
+
+    let mut labels = vec![];
+    let mut rows = vec![];
+    for path in &paths {
+        let (obsid, sources, counts) = match sources::run_source_calc(
+            path,
+            &args.islands.background.obsid_regex,
+            args.islands.background.grid,
+            args.islands.background.box_size,
+            args.islands.seed,
+            args.islands.flood,
+        ) {
+            Ok(result) => result,
+            Err(e) => {
+                eprintln!("Warning: skipping {}: {}", path.display(), e);
+                continue;
+            }
+        };
+
+        let stem = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .expect("Unable to get file's stem");
+        write_catalogue(
+            &args.output_dir.join(format!("{}_catalogue.csv", stem)),
+            &sources,
+        )?;
+
+        for count in counts {
+            labels.push(format!(
+                "{} {} {}",
+                obsid_columns(obsid, longitude),
+                count.stokes,
+                count.freq
+            ));
+            rows.push(vec![
+                count.num_islands as f64,
+                count.num_detections as f64,
+                count.num_negative as f64,
+                count.negative_fraction(),
+            ]);
+        }
+    }
+
+    write_labelled_rows(
+        &args.output_dir.join("source_counts.txt"),
+        &[
+            &format!(
+                "Islands with a pixel above {} sigma and flooded to {} sigma, counting positive",
+                args.islands.seed, args.islands.flood
+            ),
+            &format!(
+                "islands peaking above {} sigma as detections and islands of negative pixels",
+                sources::DETECTION_SNR
+            ),
+            &format!(
+                "{} stokes freq[Hz] num_islands num_above_{}sigma num_negative negative_fraction",
+                OBSID_COLUMNS,
+                sources::DETECTION_SNR
+            ),
+        ],
+        &labels,
+        &rows,
+    )?;
     Ok(())
 }
 
-fn run_cal_metrics(args: &cal_args::CalArgs) -> Result<(), Box<dyn Error>> {
-    println!("Calculating amplitude smoothness, phase RMSE, and phase average euclidean distance");
+fn run_crossmatch(
+    args: &crossmatch_args::CrossmatchArgs,
+    longitude: f64,
+) -> Result<(), Box<dyn Error>> {
+    println!("Cross-matching sources with {}", args.catalogue.display());
     let paths = resolve_paths(&args.files)?;
+    let reference = CatalogueFile {
+        file_path: args.catalogue.clone(),
+    }
+    .read(&CatalogueColumns {
+        ra: args.ra_column.clone(),
+        dec: args.dec_column.clone(),
+        flux: args.flux_column.clone(),
+        alpha: args.alpha_column.clone(),
+    })?;
+
+    let mut labels = vec![];
+    let mut rows = vec![];
+    for path in &paths {
+        let (obsid, sources, counts) = match sources::run_source_calc(
+            path,
+            &args.islands.background.obsid_regex,
+            args.islands.background.grid,
+            args.islands.background.box_size,
+            args.islands.seed,
+            args.islands.flood,
+        ) {
+            Ok(result) => result,
+            Err(e) => {
+                eprintln!("Warning: skipping {}: {}", path.display(), e);
+                continue;
+            }
+        };
+
+        let summaries = crossmatch::crossmatch(
+            &sources,
+            &counts,
+            &reference,
+            args.match_radius / 3600.0,
+            args.ref_freq,
+            args.alpha,
+        );
+        for summary in summaries {
+            labels.push(format!(
+                "{} {} {}",
+                obsid_columns(obsid, longitude),
+                summary.stokes,
+                summary.freq
+            ));
+            rows.push(vec![
+                summary.num_sources as f64,
+                summary.num_matches as f64,
+                summary.median_flux_ratio,
+                summary.flux_ratio_scatter,
+                summary.median_ra_offset,
+                summary.median_dec_offset,
+            ]);
+        }
+    }
+
+    write_labelled_rows(
+        &args.output,
+        &[
+            &format!(
+                "Sources matched within {} arcsec to {}, with reference flux densities",
+                args.match_radius,
+                args.catalogue.display()
+            ),
+            &format!(
+                "extrapolated from {} Hz. Offsets are measured minus reference [arcsec].",
+                args.ref_freq
+            ),
+            &format!(
+                "{} stokes freq[Hz] num_sources num_matches median_flux_ratio flux_ratio_scatter median_ra_offset median_dec_offset",
+                OBSID_COLUMNS
+            ),
+        ],
+        &labels,
+        &rows,
+    )?;
+    Ok(())
+}
+
+fn run_cal_metrics(args: &cal_args::CalArgs, longitude: f64) -> Result<(), Box<dyn Error>> {
+    println!(
+        "Calculating amplitude smoothness, XX/YY amplitude ratio, phase RMSE, XX-YY phase difference, flag occupancy, and gain jumps"
+    );
+    let paths = resolve_paths(&args.files)?;
+    let metafits = read_metafits(&args.metafits)?;
+    let reference = read_reference_bandpass(&args.reference_bandpass)?;
 
     let (obsids, mut xx_smooth_vecs, mut yy_smooth_vecs): (Vec<_>, Vec<_>, Vec<_>) = paths
         .iter()
-        .map(|path| gain_amplitude::run_smoothness_calc(path))
+        .map(|path| gain_amplitude::run_smoothness_calc(path, reference.as_ref()))
         .filter_map(Result::ok)
         .multiunzip();
 
-    let (_, mut dist_res_vecs, mut xx_rmse_vecs, mut yy_rmse_vecs): (
+    let (_, mut diff_mean_vecs, mut diff_rms_vecs, mut xx_rmse_vecs, mut yy_rmse_vecs): (
+        Vec<_>,
         Vec<_>,
         Vec<_>,
         Vec<_>,
@@ -80,54 +562,607 @@ fn run_cal_metrics(args: &cal_args::CalArgs) -> Result<(), Box<dyn Error>> {
         .filter_map(Result::ok)
         .multiunzip();
 
+    let (flag_obsids, mut tile_flag_vecs, mut coarse_flag_vecs, obs_flags, num_flagged_tiles): (
+        Vec<_>,
+        Vec<_>,
+        Vec<_>,
+        Vec<_>,
+        Vec<usize>,
+    ) = paths
+        .iter()
+        .map(|path| flags::run_flag_occupancy_calc(path))
+        .filter_map(Result::ok)
+        .multiunzip();
+
+    let (jump_obsids, phase_jumps, amp_jumps, jump_flagged_tiles): (
+        Vec<_>,
+        Vec<_>,
+        Vec<_>,
+        Vec<_>,
+    ) = paths
+        .iter()
+        .map(|path| jumps::run_jump_calc(path, args.jump_threshold))
+        .filter_map(Result::ok)
+        .multiunzip();
+    let count_jumps = |jumps: &[Vec<jumps::Jump>]| -> Vec<Vec<f64>> {
+        jumps
+            .iter()
+            .zip(&jump_flagged_tiles)
+            .map(|(jumps, flagged_tiles)| jumps::count_jumps(jumps, flagged_tiles))
+            .collect()
+    };
+
+    if let Some(history_path) = &args.history {
+        score_against_history(
+            history_path,
+            &paths,
+            &metafits,
+            &[
+                ("xx_gain_smoothness", &obsids, &xx_smooth_vecs),
+                ("yy_gain_smoothness", &obsids, &yy_smooth_vecs),
+                ("xx_phase_rmse", &obsids, &xx_rmse_vecs),
+                ("yy_phase_rmse", &obsids, &yy_rmse_vecs),
+                ("flag_occupancy_tile", &flag_obsids, &tile_flag_vecs),
+            ],
+            &[("flag_occupancy_obs", &flag_obsids, &obs_flags)],
+            longitude,
+        )?;
+    }
+
+    write_grouped_results(
+        "xx_gain_smoothness",
+        &obsids,
+        &xx_smooth_vecs,
+        &metafits,
+        &args.group_by,
+        longitude,
+    )?;
+    write_grouped_results(
+        "yy_gain_smoothness",
+        &obsids,
+        &yy_smooth_vecs,
+        &metafits,
+        &args.group_by,
+        longitude,
+    )?;
+    write_grouped_results(
+        "xx_phase_rmse",
+        &obsids,
+        &xx_rmse_vecs,
+        &metafits,
+        &args.group_by,
+        longitude,
+    )?;
+    write_grouped_results(
+        "yy_phase_rmse",
+        &obsids,
+        &yy_rmse_vecs,
+        &metafits,
+        &args.group_by,
+        longitude,
+    )?;
+
     write_results(
         Path::new("xx_gain_smoothness.txt"),
         &obsids,
+        longitude,
         &mut xx_smooth_vecs,
     )?;
     write_results(
         Path::new("yy_gain_smoothness.txt"),
         &obsids,
+        longitude,
         &mut yy_smooth_vecs,
     )?;
-    write_results(Path::new("xx_phase_rmse.txt"), &obsids, &mut xx_rmse_vecs)?;
-    write_results(Path::new("yy_phase_rmse.txt"), &obsids, &mut yy_rmse_vecs)?;
     write_results(
-        Path::new("euclidean_distance.txt"),
+        Path::new("xx_phase_rmse.txt"),
         &obsids,
-        &mut dist_res_vecs,
+        longitude,
+        &mut xx_rmse_vecs,
+    )?;
+    write_results(
+        Path::new("yy_phase_rmse.txt"),
+        &obsids,
+        longitude,
+        &mut yy_rmse_vecs,
+    )?;
+    write_phase_diff_results(&obsids, &mut diff_mean_vecs, &mut diff_rms_vecs, longitude)?;
+
+    write_results(
+        Path::new("flag_occupancy_tile.txt"),
+        &flag_obsids,
+        longitude,
+        &mut tile_flag_vecs,
+    )?;
+    write_results(
+        Path::new("flag_occupancy_coarse.txt"),
+        &flag_obsids,
+        longitude,
+        &mut coarse_flag_vecs,
+    )?;
+    write_results_1d(
+        Path::new("flag_occupancy_obs.txt"),
+        &flag_obsids,
+        longitude,
+        &obs_flags,
+    )?;
+    write_results_1d(
+        Path::new("num_flagged_tiles.txt"),
+        &flag_obsids,
+        longitude,
+        &num_flagged_tiles
+            .iter()
+            .map(|&n| n as f64)
+            .collect::<Vec<_>>(),
+    )?;
+    write_jumps(
+        Path::new("phase_jumps.txt"),
+        &jump_obsids,
+        longitude,
+        &phase_jumps,
+    )?;
+    write_jumps(
+        Path::new("amp_jumps.txt"),
+        &jump_obsids,
+        longitude,
+        &amp_jumps,
+    )?;
+    write_results(
+        Path::new("phase_jump_count.txt"),
+        &jump_obsids,
+        longitude,
+        &mut count_jumps(&phase_jumps),
     )?;
+    write_results(
+        Path::new("amp_jump_count.txt"),
+        &jump_obsids,
+        longitude,
+        &mut count_jumps(&amp_jumps),
+    )?;
+    write_amp_ratio_results(&paths, &metafits, args.ratio_threshold, longitude)?;
     Ok(())
 }
 
-fn run_amp_metrics(args: &cal_args::CalArgs) -> Result<(), Box<dyn Error>> {
-    println!("Calculating amplitude smoothness");
+fn run_amp_metrics(args: &cal_args::CalArgs, longitude: f64) -> Result<(), Box<dyn Error>> {
+    println!("Calculating amplitude smoothness and XX/YY amplitude ratio");
     let paths = resolve_paths(&args.files)?;
+    let metafits = read_metafits(&args.metafits)?;
+    let reference = read_reference_bandpass(&args.reference_bandpass)?;
 
     let (obsids, mut xx_smooth_vecs, mut yy_smooth_vecs): (Vec<_>, Vec<_>, Vec<_>) = paths
         .iter()
-        .map(|path| gain_amplitude::run_smoothness_calc(path))
+        .map(|path| gain_amplitude::run_smoothness_calc(path, reference.as_ref()))
         .filter_map(Result::ok)
         .multiunzip();
 
+    if let Some(history_path) = &args.history {
+        score_against_history(
+            history_path,
+            &paths,
+            &metafits,
+            &[
+                ("xx_gain_smoothness", &obsids, &xx_smooth_vecs),
+                ("yy_gain_smoothness", &obsids, &yy_smooth_vecs),
+            ],
+            &[],
+            longitude,
+        )?;
+    }
+
+    write_grouped_results(
+        "xx_gain_smoothness",
+        &obsids,
+        &xx_smooth_vecs,
+        &metafits,
+        &args.group_by,
+        longitude,
+    )?;
+    write_grouped_results(
+        "yy_gain_smoothness",
+        &obsids,
+        &yy_smooth_vecs,
+        &metafits,
+        &args.group_by,
+        longitude,
+    )?;
+
     write_results(
         Path::new("xx_gain_smoothness.txt"),
         &obsids,
+        longitude,
         &mut xx_smooth_vecs,
     )?;
     write_results(
         Path::new("yy_gain_smoothness.txt"),
         &obsids,
+        longitude,
         &mut yy_smooth_vecs,
     )?;
+    write_amp_ratio_results(&paths, &metafits, args.ratio_threshold, longitude)?;
+    Ok(())
+}
+
+fn run_phase_metrics(args: &cal_args::CalArgs, longitude: f64) -> Result<(), Box<dyn Error>> {
+    println!("Calculating phase RMSE and XX-YY phase difference");
+    let paths = resolve_paths(&args.files)?;
+    let metafits = read_metafits(&args.metafits)?;
+
+    let (obsids, mut diff_mean_vecs, mut diff_rms_vecs, mut xx_rmse_vecs, mut yy_rmse_vecs): (
+        Vec<_>,
+        Vec<_>,
+        Vec<_>,
+        Vec<_>,
+        Vec<_>,
+    ) = paths
+        .iter()
+        .map(|path| gain_phase::run_phase_calcs(path))
+        .filter_map(Result::ok)
+        .multiunzip();
+
+    if let Some(history_path) = &args.history {
+        score_against_history(
+            history_path,
+            &paths,
+            &metafits,
+            &[
+                ("xx_phase_rmse", &obsids, &xx_rmse_vecs),
+                ("yy_phase_rmse", &obsids, &yy_rmse_vecs),
+            ],
+            &[],
+            longitude,
+        )?;
+    }
+
+    write_grouped_results(
+        "xx_phase_rmse",
+        &obsids,
+        &xx_rmse_vecs,
+        &metafits,
+        &args.group_by,
+        longitude,
+    )?;
+    write_grouped_results(
+        "yy_phase_rmse",
+        &obsids,
+        &yy_rmse_vecs,
+        &metafits,
+        &args.group_by,
+        longitude,
+    )?;
+
+    write_results(
+        Path::new("xx_phase_rmse.txt"),
+        &obsids,
+        longitude,
+        &mut xx_rmse_vecs,
+    )?;
+    write_results(
+        Path::new("yy_phase_rmse.txt"),
+        &obsids,
+        longitude,
+        &mut yy_rmse_vecs,
+    )?;
+    write_phase_diff_results(&obsids, &mut diff_mean_vecs, &mut diff_rms_vecs, longitude)?;
+    Ok(())
+}
+
+fn run_stability(
+    args: &stability_args::StabilityArgs,
+    longitude: f64,
+) -> Result<(), Box<dyn Error>> {
+    println!("Calculating tile bandpass stability across observations");
+    let paths = resolve_paths(&args.files)?;
+
+    let stability = stability::run_stability_calc(&paths)?;
+
+    write_tile_ranking(
+        Path::new("tile_amp_stability.txt"),
+        "amp_rms",
+        &stability.tile_amp_rms,
+    )?;
+    write_tile_ranking(
+        Path::new("tile_phase_stability.txt"),
+        "phase_rms[rad]",
+        &stability.tile_phase_rms,
+    )?;
+
+    let mut obs_distances: Vec<_> = stability
+        .obs_amp_distance
+        .iter()
+        .zip(&stability.obs_phase_distance)
+        .map(|(&amp, &phase)| vec![amp, phase])
+        .collect();
+    write_results_with_header(
+        Path::new("obs_stability.txt"),
+        &[
+            "RMS distance of each observation's normalised bandpasses from the night median",
+            &format!("{} amp_distance phase_distance[rad]", OBSID_COLUMNS),
+        ],
+        &stability.obsids,
+        longitude,
+        &mut obs_distances,
+    )?;
+    Ok(())
+}
+
+fn run_make_bandpass(args: &bandpass_args::BandpassArgs) -> Result<(), Box<dyn Error>> {
+    println!("Calculating median bandpass");
+    let paths = resolve_paths(&args.files)?;
+
+    let (solutions, num_obs) = bandpass::median_bandpass(&paths)?;
+    write_solutions(&args.output, &solutions, &[("NOBS", num_obs as i64)])?;
+    println!(
+        "Wrote median of {} observations to {}",
+        num_obs,
+        args.output.display()
+    );
+    Ok(())
+}
+
+fn run_smooth_solutions(args: &smooth_args::SmoothArgs) -> Result<(), Box<dyn Error>> {
+    println!("Smoothing calibration solutions");
+    let paths = resolve_paths(&args.files)?;
+
+    for path in &paths {
+        let timeblocks = match smooth::run_smooth_calc(path, args.amp_order) {
+            Ok(timeblocks) => timeblocks,
+            Err(e) => {
+                eprintln!("Warning: unable to smooth {}: {}", path.display(), e);
+                continue;
+            }
+        };
+
+        let stem = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .expect("Unable to get file's stem");
+        let output = args.output_dir.join(format!("{}_smoothed.fits", stem));
+        replace_solutions(path, &output, &timeblocks)?;
+    }
+    Ok(())
+}
+
+fn run_diff_solutions(args: &diff_args::DiffArgs) -> Result<(), Box<dyn Error>> {
+    println!("Calculating gain ratio and phase difference");
+
+    let diff = diff::run_diff_calc(&args.a, &args.b)?;
+
+    // One row per tile
+    let rows: Vec<Vec<f64>> = (0..diff.ratio.num_tiles)
+        .map(|tile| {
+            [
+                &diff.median_ratio,
+                &diff.mean_phase_diff,
+                &diff.ratio_smoothness,
+                &diff.phase_diff_rmse,
+            ]
+            .iter()
+            .flat_map(|[xx, yy]| [xx[tile], yy[tile]])
+            .collect()
+        })
+        .collect();
+    let tiles: Vec<String> = (0..diff.ratio.num_tiles).map(|t| t.to_string()).collect();
+    let a = args.a.display().to_string();
+    let b = args.b.display().to_string();
+    write_labelled_rows(
+        &args.output,
+        &[
+            &format!("A: {}", a),
+            &format!("B: {}", b),
+            "tile xx_median_ratio yy_median_ratio xx_mean_phase_diff[rad] yy_mean_phase_diff[rad] \
+             xx_ratio_smoothness yy_ratio_smoothness xx_phase_diff_rmse[rad] yy_phase_diff_rmse[rad]",
+        ],
+        &tiles,
+        &rows,
+    )?;
+
+    if let Some(path) = &args.ratio_solutions {
+        write_solutions(path, &diff.ratio, &[])?;
+    }
+    Ok(())
+}
+
+fn run_pca(args: &pca_args::PcaArgs, longitude: f64) -> Result<(), Box<dyn Error>> {
+    println!("Calculating principal components of tile bandpasses");
+    let paths = resolve_paths(&args.files)?;
+
+    let (labels, amp_pca, phase_pca) = pca::run_pca_calc(&paths, args.num_modes)?;
+
+    let bandpass_labels: Vec<String> = labels
+        .iter()
+        .map(|l| format!("{} {} {}", obsid_columns(l.obsid, longitude), l.tile, l.pol))
+        .collect();
+
+    for (name, result) in [("amp", &amp_pca), ("phase", &phase_pca)] {
+        let mode_labels: Vec<String> = (0..result.modes.nrows()).map(|m| m.to_string()).collect();
+        let modes: Vec<Vec<f64>> = result
+            .modes
+            .rows()
+            .into_iter()
+            .zip(&result.explained_variance)
+            .map(|(mode, &variance)| [variance].into_iter().chain(mode.iter().copied()).collect())
+            .collect();
+        write_labelled_rows(
+            Path::new(&format!("pca_{}_modes.txt", name)),
+            &["mode explained_variance_fraction, then the eigen-bandpass per channel"],
+            &mode_labels,
+            &modes,
+        )?;
+
+        let loadings: Vec<Vec<f64>> = result
+            .loadings
+            .rows()
+            .into_iter()
+            .map(|r| r.to_vec())
+            .collect();
+        write_labelled_rows(
+            Path::new(&format!("pca_{}_loadings.txt", name)),
+            &[&format!(
+                "{} tile pol, then the loading on each mode",
+                OBSID_COLUMNS
+            )],
+            &bandpass_labels,
+            &loadings,
+        )?;
+    }
     Ok(())
 }
 
-fn run_phase_metrics(args: &cal_args::CalArgs) -> Result<(), Box<dyn Error>> {
-    println!("Calculating RMSE and average euclidean distance");
+fn run_cluster(args: &cluster_args::ClusterArgs, longitude: f64) -> Result<(), Box<dyn Error>> {
+    println!("Clustering tiles by bandpass shape");
     let paths = resolve_paths(&args.files)?;
 
-    let (obsids, mut dist_res_vecs, mut xx_rmse_vecs, mut yy_rmse_vecs): (
+    let mut tile_labels = vec![];
+    let mut distances = vec![];
+    let mut centroid_labels = vec![];
+    let mut centroids = vec![];
+
+    for path in &paths {
+        let clusters = match cluster::run_cluster_calc(path, args.num_clusters) {
+            Ok(clusters) => clusters,
+            Err(e) => {
+                eprintln!("Warning: unable to cluster {}: {}", path.display(), e);
+                continue;
+            }
+        };
+
+        for ((tile, label), &distance) in clusters
+            .tiles
+            .iter()
+            .zip(&clusters.labels)
+            .zip(&clusters.distances)
+        {
+            tile_labels.push(format!(
+                "{} {} {}",
+                obsid_columns(clusters.obsid, longitude),
+                tile,
+                label
+            ));
+            distances.push(vec![distance]);
+        }
+        for ((cluster, centroid), size) in clusters
+            .centroids
+            .rows()
+            .into_iter()
+            .enumerate()
+            .zip(clusters.sizes())
+        {
+            centroid_labels.push(format!(
+                "{} {} {}",
+                obsid_columns(clusters.obsid, longitude),
+                cluster,
+                size
+            ));
+            centroids.push(centroid.to_vec());
+        }
+    }
+
+    write_labelled_rows(
+        Path::new("tile_clusters.txt"),
+        &[&format!(
+            "{} tile cluster distance_to_centroid",
+            OBSID_COLUMNS
+        )],
+        &tile_labels,
+        &distances,
+    )?;
+    write_labelled_rows(
+        Path::new("cluster_centroids.txt"),
+        &[
+            &format!(
+                "{} cluster num_tiles, then the centroid bandpass: XX amplitude,",
+                OBSID_COLUMNS
+            ),
+            "YY amplitude, XX phase residual and YY phase residual, each over all channels",
+        ],
+        &centroid_labels,
+        &centroids,
+    )?;
+    Ok(())
+}
+
+fn run_summarize(args: &summary_args::SummaryArgs, longitude: f64) -> Result<(), Box<dyn Error>> {
+    println!("Summarising calibration metrics");
+
+    let (metrics, num_flagged_tiles, obs_flags) = match &args.results_dir {
+        Some(dir) => read_summary_metrics(dir)?,
+        None => calc_summary_metrics(&resolve_paths(&args.files)?, args.jump_threshold),
+    };
+    let summary = summary::summarise(&metrics, &num_flagged_tiles, &obs_flags);
+
+    write_summary(&args.output, &summary::SUMMARY_METRICS, &summary, longitude)?;
+
+    let stem = args
+        .output
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("summary");
+    let night_path = args.output.with_file_name(format!("{}_night.txt", stem));
+    write_night_summary(&night_path, &summary::SUMMARY_METRICS, &summary)?;
+    Ok(())
+}
+
+/// Per-tile metrics in the order of `SUMMARY_METRICS`, with the number of
+/// flagged tiles and flag occupancy of each observation
+type SummaryMetrics = (
+    Vec<summary::TileValues>,
+    summary::ObsValues,
+    summary::ObsValues,
+);
+
+/// Read the metrics to summarise from the results files of a cal-metrics run
+fn read_summary_metrics(dir: &Path) -> Result<SummaryMetrics, Box<dyn Error>> {
+    // A missing results file leaves its metric out rather than failing the
+    // whole summary, e.g. when only some metrics were calculated
+    let read = |name: &str| -> Result<summary::TileValues, Box<dyn Error>> {
+        let file_path = dir.join(format!("{}.txt", name));
+        if !file_path.exists() {
+            eprintln!(
+                "Warning: no {}, leaving it out of the summary",
+                file_path.display()
+            );
+            return Ok((vec![], vec![]));
+        }
+        ResultsFile { file_path }.read()
+    };
+
+    let metrics = summary::SUMMARY_METRICS
+        .iter()
+        .map(|name| read(name))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let read_1d = |name: &str| -> Result<summary::ObsValues, Box<dyn Error>> {
+        let (obsids, rows) = read(name)?;
+        let values = rows
+            .iter()
+            .map(|row| row.first().copied().unwrap_or(f64::NAN))
+            .collect();
+        Ok((obsids, values))
+    };
+
+    Ok((
+        metrics,
+        read_1d("num_flagged_tiles")?,
+        read_1d("flag_occupancy_obs")?,
+    ))
+}
+
+/// Recompute the metrics to summarise from solutions
+fn calc_summary_metrics(paths: &[PathBuf], jump_threshold: f64) -> SummaryMetrics {
+    let (smooth_obsids, xx_smooth_vecs, yy_smooth_vecs): (Vec<_>, Vec<_>, Vec<_>) = paths
+        .iter()
+        .map(|path| gain_amplitude::run_smoothness_calc(path, None))
+        .filter_map(Result::ok)
+        .multiunzip();
+
+    let (ratio_obsids, ratio_vecs): (Vec<_>, Vec<_>) = paths
+        .iter()
+        .map(|path| gain_amplitude::run_amp_ratio_calc(path))
+        .filter_map(Result::ok)
+        .unzip();
+
+    let (phase_obsids, diff_mean_vecs, diff_rms_vecs, xx_rmse_vecs, yy_rmse_vecs): (
+        Vec<_>,
         Vec<_>,
         Vec<_>,
         Vec<_>,
@@ -138,13 +1173,321 @@ fn run_phase_metrics(args: &cal_args::CalArgs) -> Result<(), Box<dyn Error>> {
         .filter_map(Result::ok)
         .multiunzip();
 
-    write_results(Path::new("xx_phase_rmse.txt"), &obsids, &mut xx_rmse_vecs)?;
-    write_results(Path::new("yy_phase_rmse.txt"), &obsids, &mut yy_rmse_vecs)?;
+    let (jump_obsids, phase_jump_counts, amp_jump_counts): (Vec<_>, Vec<_>, Vec<_>) = paths
+        .iter()
+        .map(|path| jumps::run_jump_calc(path, jump_threshold))
+        .filter_map(Result::ok)
+        .map(|(obsid, phase_jumps, amp_jumps, flagged_tiles)| {
+            (
+                obsid,
+                jumps::count_jumps(&phase_jumps, &flagged_tiles),
+                jumps::count_jumps(&amp_jumps, &flagged_tiles),
+            )
+        })
+        .multiunzip();
+
+    let (flag_obsids, tile_flag_vecs, _, obs_flags, num_flagged_tiles): (
+        Vec<_>,
+        Vec<_>,
+        Vec<_>,
+        Vec<_>,
+        Vec<usize>,
+    ) = paths
+        .iter()
+        .map(|path| flags::run_flag_occupancy_calc(path))
+        .filter_map(Result::ok)
+        .multiunzip();
+
+    (
+        vec![
+            (smooth_obsids.clone(), xx_smooth_vecs),
+            (smooth_obsids, yy_smooth_vecs),
+            (ratio_obsids, ratio_vecs),
+            (phase_obsids.clone(), xx_rmse_vecs),
+            (phase_obsids.clone(), yy_rmse_vecs),
+            (phase_obsids.clone(), diff_mean_vecs),
+            (phase_obsids, diff_rms_vecs),
+            (jump_obsids.clone(), phase_jump_counts),
+            (jump_obsids, amp_jump_counts),
+            (flag_obsids.clone(), tile_flag_vecs),
+        ],
+        (
+            flag_obsids.clone(),
+            num_flagged_tiles.iter().map(|&n| n as f64).collect(),
+        ),
+        (flag_obsids, obs_flags),
+    )
+}
+
+/// Per-tile metric name with the obsids and per-tile values of each observation
+type TileMetric<'a> = (&'a str, &'a [usize], &'a [Vec<f64>]);
+
+/// Per-observation metric name with the obsids and value of each observation
+type ObsMetric<'a> = (&'a str, &'a [usize], &'a [f64]);
+
+/// Write each metric as z-scores against the history at `history_path`, to
+/// `<metric>_zscore.txt`, then add the new values to the history
+fn score_against_history(
+    history_path: &Path,
+    paths: &[PathBuf],
+    metafits: &[Metafits],
+    tile_metrics: &[TileMetric],
+    obs_metrics: &[ObsMetric],
+    longitude: f64,
+) -> Result<(), Box<dyn Error>> {
+    let mut metric_history = HistoryFile {
+        file_path: history_path.to_path_buf(),
+    }
+    .read()?;
+
+    // Tiles are recorded by name, so tiles are compared with themselves even
+    // if the tile order changes between observations
+    let tile_names = read_tile_names(paths, metafits);
+    let tile_name = |obsid: usize, tile: usize| -> String {
+        tile_names
+            .get(&obsid)
+            .and_then(|names| names.get(tile))
+            .cloned()
+            .unwrap_or_else(|| tile.to_string())
+    };
+
+    // Score everything before recording anything, so that observations in
+    // this run aren't scored against each other
+    let mut tile_scores: Vec<Vec<Vec<f64>>> = tile_metrics
+        .iter()
+        .map(|&(name, obsids, values)| {
+            obsids
+                .iter()
+                .zip(values)
+                .map(|(&obsid, tile_values)| {
+                    tile_values
+                        .iter()
+                        .enumerate()
+                        .map(|(tile, &value)| {
+                            let tile = tile_name(obsid, tile);
+                            history::z_score(&metric_history, name, Some(&tile), obsid, value)
+                        })
+                        .collect()
+                })
+                .collect()
+        })
+        .collect();
+    let obs_scores: Vec<Vec<f64>> = obs_metrics
+        .iter()
+        .map(|&(name, obsids, values)| {
+            obsids
+                .iter()
+                .zip(values)
+                .map(|(&obsid, &value)| history::z_score(&metric_history, name, None, obsid, value))
+                .collect()
+        })
+        .collect();
+
+    for (&(name, obsids, _), scores) in tile_metrics.iter().zip(&mut tile_scores) {
+        write_results(
+            Path::new(&format!("{}_zscore.txt", name)),
+            obsids,
+            longitude,
+            scores,
+        )?;
+    }
+    for (&(name, obsids, _), scores) in obs_metrics.iter().zip(&obs_scores) {
+        write_results_1d(
+            Path::new(&format!("{}_zscore.txt", name)),
+            obsids,
+            longitude,
+            scores,
+        )?;
+    }
+
+    for &(name, obsids, values) in tile_metrics {
+        for (&obsid, tile_values) in obsids.iter().zip(values) {
+            for (tile, &value) in tile_values.iter().enumerate() {
+                let tile = tile_name(obsid, tile);
+                history::record(&mut metric_history, name, Some(&tile), obsid, value);
+            }
+        }
+    }
+    for &(name, obsids, values) in obs_metrics {
+        for (&obsid, &value) in obsids.iter().zip(values) {
+            history::record(&mut metric_history, name, None, obsid, value);
+        }
+    }
+    write_history(history_path, &metric_history)?;
+    Ok(())
+}
+
+fn write_amp_ratio_results(
+    paths: &[PathBuf],
+    metafits: &[Metafits],
+    threshold: f64,
+    longitude: f64,
+) -> Result<(), Box<dyn Error>> {
+    let (obsids, mut ratio_vecs): (Vec<_>, Vec<_>) = paths
+        .iter()
+        .map(|path| gain_amplitude::run_amp_ratio_calc(path))
+        .filter_map(Result::ok)
+        .unzip();
+
+    let flags: Vec<_> = obsids
+        .iter()
+        .zip(&ratio_vecs)
+        .map(|(&obsid, ratios)| {
+            gain_amplitude::flag_amp_ratios(ratios, find_metafits(metafits, obsid), threshold)
+        })
+        .collect();
+
+    write_ratio_flags(
+        Path::new("xx_yy_amp_ratio_flags.txt"),
+        &obsids,
+        longitude,
+        &flags,
+    )?;
     write_results(
-        Path::new("euclidean_distance.txt"),
+        Path::new("xx_yy_amp_ratio.txt"),
         &obsids,
-        &mut dist_res_vecs,
+        longitude,
+        &mut ratio_vecs,
+    )?;
+    Ok(())
+}
+
+fn write_phase_diff_results(
+    obsids: &[usize],
+    diff_mean_vecs: &mut [Vec<f64>],
+    diff_rms_vecs: &mut [Vec<f64>],
+    longitude: f64,
+) -> Result<(), Box<dyn Error>> {
+    let description = [
+        "Differences are wrapped to [-pi, pi] and flagged channels are ignored. One",
+        "column per tile, NaN if flagged.",
+        &format!("{}, then the value for each tile", OBSID_COLUMNS),
+    ];
+
+    let mean_header = [
+        &[
+            "XX-YY phase difference circular mean [rad], the phase offset between the",
+            "polarisations, taken over channels before any fit is removed.",
+        ],
+        &description[..],
+    ]
+    .concat();
+    write_results_with_header(
+        Path::new("xx_yy_phase_diff_mean.txt"),
+        &mean_header,
+        obsids,
+        longitude,
+        diff_mean_vecs,
     )?;
+
+    let rms_header = [
+        &[
+            "XX-YY phase difference RMS [rad]. A straight line against channel is fit to",
+            "and removed from each polarisation's unwrapped phase before differencing.",
+        ],
+        &description[..],
+    ]
+    .concat();
+    write_results_with_header(
+        Path::new("xx_yy_phase_diff_rms.txt"),
+        &rms_header,
+        obsids,
+        longitude,
+        diff_rms_vecs,
+    )?;
+    Ok(())
+}
+
+fn read_reference_bandpass(
+    files: &[PathBuf],
+) -> Result<Option<gain_amplitude::ReferenceBandpass>, Box<dyn Error>> {
+    if files.is_empty() {
+        return Ok(None);
+    }
+
+    let paths = resolve_paths(files)?;
+    Ok(Some(gain_amplitude::build_reference_bandpass(&paths)?))
+}
+
+fn read_metafits(files: &[PathBuf]) -> Result<Vec<Metafits>, Box<dyn Error>> {
+    files
+        .iter()
+        .map(|path| {
+            MetafitsFile {
+                file_path: path.to_path_buf(),
+            }
+            .read_fits()
+        })
+        .collect()
+}
+
+/// Name of each tile of each observation, from the TILES HDU of its solutions
+/// or else its metafits. Observations with neither are left out.
+fn read_tile_names(paths: &[PathBuf], metafits: &[Metafits]) -> BTreeMap<usize, Vec<String>> {
+    paths
+        .iter()
+        .filter_map(|path| {
+            CalSolFile {
+                file_path: path.to_path_buf(),
+            }
+            .read_fits()
+            .ok()
+        })
+        .filter_map(|solutions| {
+            let names = solutions.tile_names.or_else(|| {
+                find_metafits(metafits, solutions.id)
+                    .map(|m| m.tiles.iter().map(|t| t.name.clone()).collect())
+            })?;
+            Some((solutions.id, names))
+        })
+        .collect()
+}
+
+fn find_metafits(metafits: &[Metafits], obsid: usize) -> Option<&Metafits> {
+    // A single metafits is used for every observation
+    match metafits {
+        [single] => Some(single),
+        _ => metafits.iter().find(|m| m.id == obsid),
+    }
+}
+
+/// Write a per-tile metric aggregated over each requested tile grouping
+fn write_grouped_results(
+    name: &str,
+    obsids: &[usize],
+    results: &[Vec<f64>],
+    metafits: &[Metafits],
+    groupings: &[TileGrouping],
+    longitude: f64,
+) -> Result<(), Box<dyn Error>> {
+    for grouping in groupings {
+        let mut grouped_obsids = vec![];
+        let mut grouped_results = vec![];
+
+        for (&obsid, values) in obsids.iter().zip(results) {
+            let Some(obs_metafits) = find_metafits(metafits, obsid) else {
+                eprintln!("Warning: no metafits for obsid {}, not grouping", obsid);
+                continue;
+            };
+
+            match group_tile_metric(values, obs_metafits, *grouping) {
+                Ok(groups) => {
+                    grouped_obsids.push(obsid);
+                    grouped_results.push(groups);
+                }
+                Err(e) => eprintln!("Warning: unable to group obsid {}: {}", obsid, e),
+            }
+        }
+
+        let path = format!("{}_by_{}.txt", name, grouping.name());
+        write_group_results(
+            Path::new(&path),
+            &grouped_obsids,
+            longitude,
+            &grouped_results,
+        )?;
+    }
+
     Ok(())
 }
 
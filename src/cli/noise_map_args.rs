@@ -0,0 +1,18 @@
+use crate::cli::background_args::BackgroundArgs;
+use clap::Args;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+#[clap(arg_required_else_help = true)]
+pub(crate) struct NoiseMapArgs {
+    #[arg(short, long, num_args=1..,)]
+    pub(super) files: Vec<PathBuf>,
+
+    #[command(flatten)]
+    pub(super) background: BackgroundArgs,
+
+    /// Directory for the maps, named <input stem>_bkg.fits and
+    /// <input stem>_rms.fits, and the noise_maps.txt summary
+    #[arg(short, long, default_value = ".")]
+    pub(super) output_dir: PathBuf,
+}
@@ -0,0 +1,13 @@
+use clap::Args;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+#[clap(arg_required_else_help = true)]
+pub(crate) struct PcaArgs {
+    #[arg(short, long, num_args=1..,)]
+    pub(super) files: Vec<PathBuf>,
+
+    /// Number of leading modes to keep
+    #[arg(short, long, default_value_t = 5)]
+    pub(super) num_modes: usize,
+}
@@ -0,0 +1,17 @@
+use clap::Args;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+#[clap(arg_required_else_help = true)]
+pub(crate) struct SmoothArgs {
+    #[arg(short, long, num_args=1..,)]
+    pub(super) files: Vec<PathBuf>,
+
+    /// Order of the polynomial fit to each tile's amplitude
+    #[arg(short, long, default_value_t = 5)]
+    pub(super) amp_order: usize,
+
+    /// Directory for the smoothed solutions, named <input stem>_smoothed.fits
+    #[arg(short, long, default_value = ".")]
+    pub(super) output_dir: PathBuf,
+}
@@ -0,0 +1,18 @@
+use crate::cli::background_args::IslandArgs;
+use clap::Args;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+#[clap(arg_required_else_help = true)]
+pub(crate) struct SourceArgs {
+    #[arg(short, long, num_args=1..,)]
+    pub(super) files: Vec<PathBuf>,
+
+    #[command(flatten)]
+    pub(super) islands: IslandArgs,
+
+    /// Directory for the catalogues, named <input stem>_catalogue.csv, and
+    /// the source_counts.txt summary
+    #[arg(short, long, default_value = ".")]
+    pub(super) output_dir: PathBuf,
+}
@@ -0,0 +1,27 @@
+use clap::{ArgGroup, Args};
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+#[clap(arg_required_else_help = true)]
+#[clap(group(ArgGroup::new("input").required(true).args(["files", "results_dir"])))]
+pub(crate) struct SummaryArgs {
+    /// Solutions to recompute the calibration metrics from
+    #[arg(short, long, num_args=1..,)]
+    pub(super) files: Vec<PathBuf>,
+
+    /// Directory with the results files of a previous cal-metrics run, used
+    /// instead of recomputing the metrics. Metrics whose files are missing
+    /// are left out.
+    #[arg(short = 'd', long)]
+    pub(super) results_dir: Option<PathBuf>,
+
+    /// Significance of the gain jumps counted when recomputing the metrics,
+    /// as in cal-metrics
+    #[arg(long, default_value_t = 5.0)]
+    pub(super) jump_threshold: f64,
+
+    /// Path of the per-observation summary table. The night-wide summary is
+    /// written next to it with a _night suffix.
+    #[arg(short, long, default_value = "summary.txt")]
+    pub(super) output: PathBuf,
+}
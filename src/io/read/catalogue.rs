@@ -0,0 +1,266 @@
+use fitsio::FitsFile;
+use fitsio::hdu::HduInfo;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+/// A source from a reference catalogue
+#[derive(Debug, Clone)]
+pub(crate) struct ReferenceSource {
+    // Position [deg]
+    pub(crate) ra: f64,
+    pub(crate) dec: f64,
+
+    // Flux density at the catalogue's reference frequency [Jy]
+    pub(crate) flux: f64,
+
+    // Spectral index, NaN if the catalogue doesn't have one for this source
+    pub(crate) alpha: f64,
+}
+
+/// Names of the catalogue columns to read, matched ignoring case
+#[derive(Debug)]
+pub(crate) struct CatalogueColumns {
+    pub(crate) ra: String,
+    pub(crate) dec: String,
+    pub(crate) flux: String,
+
+    // Optional, all spectral indices are NaN if the column is missing
+    pub(crate) alpha: String,
+}
+
+/// Struct for holding path to a reference catalogue with methods for
+/// reading. Catalogues are either FITS files with a table in the first
+/// extension, like GLEAM's, or CSV files with a header row. Lines of CSV
+/// files starting with # are ignored.
+pub(crate) struct CatalogueFile {
+    pub(crate) file_path: PathBuf,
+}
+
+impl CatalogueFile {
+    pub(crate) fn read(
+        &self,
+        columns: &CatalogueColumns,
+    ) -> Result<Vec<ReferenceSource>, Box<dyn Error>> {
+        let is_fits = self
+            .file_path
+            .extension()
+            .and_then(|s| s.to_str())
+            .is_some_and(|ext| ["fits", "fit", "fts"].contains(&ext.to_lowercase().as_str()));
+        let [ra, dec, flux, alpha] = if is_fits {
+            self.read_fits(columns)?
+        } else {
+            self.read_csv(columns)?
+        };
+
+        Ok(ra
+            .into_iter()
+            .zip(dec)
+            .zip(flux)
+            .zip(alpha)
+            .map(|(((ra, dec), flux), alpha)| ReferenceSource {
+                ra,
+                dec,
+                flux,
+                alpha,
+            })
+            .filter(|s| s.ra.is_finite() && s.dec.is_finite() && s.flux.is_finite())
+            .collect())
+    }
+
+    fn read_fits(&self, columns: &CatalogueColumns) -> Result<[Vec<f64>; 4], Box<dyn Error>> {
+        let mut fptr = FitsFile::open(&self.file_path)?;
+        let hdu = fptr.hdu(1)?;
+        let HduInfo::TableInfo {
+            column_descriptions,
+            ..
+        } = &hdu.info
+        else {
+            return Err(format!("{} has no table in HDU 1", self.file_path.display()).into());
+        };
+
+        // Find the column names as written, since fitsio matches them exactly
+        let find = |name: &str| {
+            column_descriptions
+                .iter()
+                .find(|c| c.name.eq_ignore_ascii_case(name))
+        };
+        let mut read = |name: &str| -> Result<Vec<f64>, Box<dyn Error>> {
+            let column = find(name)
+                .ok_or_else(|| format!("No {} column in {}", name, self.file_path.display()))?;
+            Ok(hdu.read_col(&mut fptr, &column.name)?)
+        };
+        let ra = read(&columns.ra)?;
+        let dec = read(&columns.dec)?;
+        let flux = read(&columns.flux)?;
+        let alpha = match find(&columns.alpha) {
+            Some(_) => read(&columns.alpha)?,
+            None => vec![f64::NAN; ra.len()],
+        };
+
+        Ok([ra, dec, flux, alpha])
+    }
+
+    fn read_csv(&self, columns: &CatalogueColumns) -> Result<[Vec<f64>; 4], Box<dyn Error>> {
+        let contents = fs::read_to_string(&self.file_path)?;
+        let mut lines = contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'));
+
+        let (_, header) = lines
+            .next()
+            .ok_or_else(|| format!("{} is empty", self.file_path.display()))?;
+        let names: Vec<String> = header
+            .split(',')
+            .map(|name| name.trim().trim_matches('"').to_lowercase())
+            .collect();
+        let index = |name: &str| names.iter().position(|n| *n == name.to_lowercase());
+        let missing = |name: &str| format!("No {} column in {}", name, self.file_path.display());
+        let indices = [
+            index(&columns.ra).ok_or_else(|| missing(&columns.ra))?,
+            index(&columns.dec).ok_or_else(|| missing(&columns.dec))?,
+            index(&columns.flux).ok_or_else(|| missing(&columns.flux))?,
+        ];
+        let alpha_index = index(&columns.alpha);
+
+        let mut values: [Vec<f64>; 4] = Default::default();
+        for (line_num, line) in lines {
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let invalid = || {
+                format!(
+                    "Invalid line {} in catalogue {}",
+                    line_num + 1,
+                    self.file_path.display()
+                )
+            };
+            for (column, &i) in indices.iter().enumerate() {
+                let value = fields
+                    .get(i)
+                    .and_then(|v| v.parse().ok())
+                    .ok_or_else(invalid)?;
+                values[column].push(value);
+            }
+            // Blank spectral indices fall back to the default
+            let alpha = alpha_index
+                .and_then(|i| fields.get(i))
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(f64::NAN);
+            values[3].push(alpha);
+        }
+
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fitsio::tables::{ColumnDataType, ColumnDescription};
+    use std::path::Path;
+
+    fn columns() -> CatalogueColumns {
+        CatalogueColumns {
+            ra: "ra".to_string(),
+            dec: "dec".to_string(),
+            flux: "flux".to_string(),
+            alpha: "alpha".to_string(),
+        }
+    }
+
+    fn read_csv(name: &str, contents: &str) -> Result<Vec<ReferenceSource>, Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("calmet_{}_{}.csv", name, std::process::id()));
+        fs::write(&path, contents)?;
+        let result = CatalogueFile {
+            file_path: path.clone(),
+        }
+        .read(&columns());
+        fs::remove_file(&path)?;
+        result
+    }
+
+    #[test]
+    fn reads_csv() {
+        let sources = read_csv(
+            "cat_csv",
+            "# A comment\n\"RA\", Dec,name,Flux,Alpha\n10.5,-20,a,1.5,-0.8\n\n11,-21,b,2,\n12,NaN,c,3,0.1\n",
+        )
+        .unwrap();
+
+        // The NaN Dec is dropped, the blank spectral index is NaN
+        assert_eq!(sources.len(), 2);
+        assert_eq!(
+            (
+                sources[0].ra,
+                sources[0].dec,
+                sources[0].flux,
+                sources[0].alpha
+            ),
+            (10.5, -20.0, 1.5, -0.8)
+        );
+        assert_eq!((sources[1].ra, sources[1].flux), (11.0, 2.0));
+        assert!(sources[1].alpha.is_nan());
+    }
+
+    #[test]
+    fn csv_without_alpha_or_required_columns() {
+        let sources = read_csv("cat_no_alpha", "ra,dec,flux\n1,2,3\n").unwrap();
+        assert_eq!(sources.len(), 1);
+        assert!(sources[0].alpha.is_nan());
+
+        assert!(read_csv("cat_no_flux", "ra,dec\n1,2\n").is_err());
+        assert!(read_csv("cat_bad_line", "ra,dec,flux\n1,2,x\n").is_err());
+        assert!(read_csv("cat_empty", "# Only a comment\n").is_err());
+    }
+
+    fn write_fits(path: &Path, alpha_type: Option<ColumnDataType>) -> Result<(), Box<dyn Error>> {
+        let _ = fs::remove_file(path);
+        let mut fptr = FitsFile::create(path).open()?;
+        let mut descriptions = vec![];
+        for name in ["RA", "Dec", "Flux"] {
+            descriptions.push(
+                ColumnDescription::new(name)
+                    .with_type(ColumnDataType::Double)
+                    .create()?,
+            );
+        }
+        if let Some(data_type) = alpha_type {
+            descriptions.push(
+                ColumnDescription::new("Alpha")
+                    .with_type(data_type)
+                    .that_repeats(8)
+                    .create()?,
+            );
+        }
+        let hdu = fptr.create_table("CATALOGUE", &descriptions)?;
+        hdu.write_col(&mut fptr, "RA", &[10.0, 20.0])?;
+        hdu.write_col(&mut fptr, "Dec", &[-30.0, -40.0])?;
+        hdu.write_col(&mut fptr, "Flux", &[1.0, 2.0])?;
+        if alpha_type.is_some() {
+            hdu.write_col(&mut fptr, "Alpha", &["bad".to_string(), "bad".to_string()])?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn fits_alpha_only_falls_back_when_missing() {
+        let path = std::env::temp_dir().join(format!("calmet_cat_{}.fits", std::process::id()));
+        let file = CatalogueFile {
+            file_path: path.clone(),
+        };
+
+        write_fits(&path, None).unwrap();
+        let sources = file.read(&columns()).unwrap();
+        assert_eq!(sources.len(), 2);
+        assert_eq!(
+            (sources[1].ra, sources[1].dec, sources[1].flux),
+            (20.0, -40.0, 2.0)
+        );
+        assert!(sources.iter().all(|s| s.alpha.is_nan()));
+
+        // A spectral index column that can't be read is an error
+        write_fits(&path, Some(ColumnDataType::String)).unwrap();
+        assert!(file.read(&columns()).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
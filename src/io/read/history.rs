@@ -0,0 +1,67 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// Metric values from previous runs. Keyed by metric name and tile name, with
+/// no tile for per-observation metrics, then by obsid. Tiles are keyed by
+/// name since the tile order can change between observations.
+#[derive(Debug, Default)]
+pub(crate) struct History {
+    pub(crate) values: BTreeMap<(String, Option<String>), BTreeMap<usize, f64>>,
+}
+
+/// Struct for holding path to a history file with methods for reading.
+///
+/// The file is plain text with one `metric obsid tile value` line per value,
+/// where the tile is the tile name, or `-` for per-observation metrics.
+pub(crate) struct HistoryFile {
+    pub(crate) file_path: PathBuf,
+}
+
+impl HistoryFile {
+    /// Read the history, which is empty if the file doesn't exist yet
+    pub(crate) fn read(&self) -> Result<History, Box<dyn Error>> {
+        let contents = match fs::read_to_string(&self.file_path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(History::default()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut history = History::default();
+        for (line_num, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || {
+                format!(
+                    "Invalid line {} in history file {}",
+                    line_num + 1,
+                    self.file_path.display()
+                )
+            };
+            let [metric, obsid, tile, value] = line
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .try_into()
+                .map_err(|_| invalid())?;
+            let obsid: usize = obsid.parse().map_err(|_| invalid())?;
+            let tile = match tile {
+                "-" => None,
+                tile => Some(tile.to_string()),
+            };
+            let value: f64 = value.parse().map_err(|_| invalid())?;
+
+            history
+                .values
+                .entry((metric.to_string(), tile))
+                .or_default()
+                .insert(obsid, value);
+        }
+
+        Ok(history)
+    }
+}
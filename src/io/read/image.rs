@@ -1,11 +1,16 @@
+use super::wcs::Wcs;
+use crate::time::gps_from_utc;
 use fitsio::FitsFile;
+use fitsio::hdu::FitsHdu;
 use ndarray::prelude::*;
+use regex::Regex;
 use std::{error::Error, path::PathBuf};
 
 #[derive(Debug)]
 pub(crate) struct Image {
-    // Holds image data
-    pub(crate) data: Array2<f64>,
+    // Image planes indexed [stokes, freq, y, x], where x is along RA and y
+    // along Dec. Axes missing from the file have length 1.
+    pub(crate) data: Array4<f64>,
 
     // Holds MWA observation ID
     pub(crate) id: usize,
@@ -15,10 +20,57 @@ pub(crate) struct Image {
 
     // Number of pixels in the y direction
     pub(crate) num_pixels_y: usize,
+
+    // Stokes parameter of each plane along the first axis, using the FITS
+    // codes (1 = I, 2 = Q, ...). NaN if the file has no STOKES axis.
+    pub(crate) stokes: Vec<f64>,
+
+    // Frequency of each plane along the second axis [Hz], NaN if the file has
+    // no FREQ axis
+    pub(crate) freqs: Vec<f64>,
+
+    // Celestial coordinates of the x and y pixels, None if the file has no RA
+    // and Dec axes
+    pub(crate) wcs: Option<Wcs>,
+
+    // Restoring beam major and minor axis FWHM and position angle [deg] from
+    // BMAJ, BMIN and BPA, None if they're missing
+    pub(crate) beam: Option<[f64; 3]>,
+}
+
+/// Image axes recognised from their CTYPE
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AxisType {
+    Ra,
+    Dec,
+    Freq,
+    Stokes,
+    Other,
+}
+
+impl AxisType {
+    fn from_ctype(ctype: &str) -> Self {
+        let ctype = ctype.trim().to_uppercase();
+        if ctype.starts_with("RA") {
+            AxisType::Ra
+        } else if ctype.starts_with("DEC") {
+            AxisType::Dec
+        } else if ctype.starts_with("FREQ") {
+            AxisType::Freq
+        } else if ctype.starts_with("STOKES") {
+            AxisType::Stokes
+        } else {
+            AxisType::Other
+        }
+    }
 }
 
 pub(crate) struct ImageFile {
     pub(crate) file_path: PathBuf,
+
+    // Pattern for the obsid in the file name, used if the headers don't give
+    // one. The first capture group, or else the whole match, is the obsid.
+    pub(crate) obsid_pattern: Regex,
 }
 
 impl ImageFile {
@@ -28,40 +80,286 @@ impl ImageFile {
             // Do something
         }
 
-        let stem = self
-            .file_path
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .expect("Unable to get file's stem");
+        let mut fptr = FitsFile::open(&self.file_path)?;
 
-        let (gps, _) = stem
-            .split_once("_")
-            .expect("Could not split image file name to get obsid");
+        let img_hdu = fptr.hdu(0)?;
+        let id = self.read_obsid(&mut fptr, &img_hdu)?;
 
-        let gps_num: usize = gps.parse()?;
+        let mut raw_data: ArrayD<f64> = img_hdu.read_image(&mut fptr)?;
 
-        let mut fptr = FitsFile::open(&self.file_path)?;
+        // Integer images mark missing pixels with BLANK, which is in stored
+        // units so has to be scaled like the data
+        if let Ok(blank) = img_hdu.read_key::<i64>(&mut fptr, "BLANK") {
+            let bscale = img_hdu.read_key::<f64>(&mut fptr, "BSCALE").unwrap_or(1.0);
+            let bzero = img_hdu.read_key::<f64>(&mut fptr, "BZERO").unwrap_or(0.0);
+            let blank = blank as f64 * bscale + bzero;
+            raw_data.mapv_inplace(|v| if v == blank { f64::NAN } else { v });
+        }
+        let num_axes = raw_data.ndim();
+        if num_axes < 2 {
+            return Err(format!(
+                "{} has {} image axes, need at least 2",
+                self.file_path.display(),
+                num_axes
+            )
+            .into());
+        }
 
-        let img_hdu = fptr.hdu(0)?;
+        // Type of each FITS axis, from axis 1
+        let fits_axis_types: Vec<AxisType> = (1..=num_axes)
+            .map(|n| {
+                img_hdu
+                    .read_key::<String>(&mut fptr, &format!("CTYPE{}", n))
+                    .map_or(AxisType::Other, |ctype| AxisType::from_ctype(&ctype))
+            })
+            .collect();
+        let fits_axis = |axis_type: AxisType| {
+            fits_axis_types
+                .iter()
+                .position(|&t| t == axis_type)
+                .map(|i| i + 1)
+        };
+        let stokes_axis = fits_axis(AxisType::Stokes);
+        let freq_axis = fits_axis(AxisType::Freq);
+        let wcs = match (fits_axis(AxisType::Ra), fits_axis(AxisType::Dec)) {
+            // Pixel metrics don't need the WCS, so carry on without one
+            (Some(ra_axis), Some(dec_axis)) => Wcs::read(&mut fptr, &img_hdu, ra_axis, dec_axis)
+                .inspect_err(|e| {
+                    eprintln!(
+                        "Warning: ignoring the WCS of {}: {}",
+                        self.file_path.display(),
+                        e
+                    )
+                })
+                .ok(),
+            _ => None,
+        };
 
-        // let id: i64 = img_hdu.read_key(&mut fptr, "OBSID")?;
+        // Array axes are in the reverse order of the FITS axes
+        let mut axis_types: Vec<AxisType> = fits_axis_types.iter().rev().copied().collect();
 
-        let raw_data: ArrayD<f64> = img_hdu.read_image(&mut fptr)?;
-        let dim = raw_data.dim();
-        assert_eq!(dim[0], 1);
-        assert_eq!(dim[1], 1);
-        let num_pixels_x = dim[2];
-        let num_pixels_y = dim[3];
+        // Without celestial CTYPEs, e.g. for plain 2D images, take the first
+        // unrecognised FITS axes to be x then y
+        for sky_axis in [AxisType::Ra, AxisType::Dec] {
+            if !axis_types.contains(&sky_axis)
+                && let Some(other) = axis_types.iter().rposition(|&t| t == AxisType::Other)
+            {
+                axis_types[other] = sky_axis;
+            }
+        }
+
+        // Drop any other degenerate axes
+        let mut data = raw_data;
+        for axis in (0..num_axes).rev() {
+            if axis_types[axis] != AxisType::Other {
+                continue;
+            }
+            if data.len_of(Axis(axis)) != 1 {
+                return Err(format!(
+                    "{} has an unrecognised image axis with {} planes",
+                    self.file_path.display(),
+                    data.len_of(Axis(axis))
+                )
+                .into());
+            }
+            data = data.index_axis_move(Axis(axis), 0);
+            axis_types.remove(axis);
+        }
 
-        let data = raw_data.slice_move(s![0, 0, .., ..]);
+        // Add missing axes, then put them all in the order of `Image::data`
+        let order = [
+            AxisType::Stokes,
+            AxisType::Freq,
+            AxisType::Dec,
+            AxisType::Ra,
+        ];
+        for axis_type in order {
+            if !axis_types.contains(&axis_type) {
+                let new_axis = data.ndim();
+                data.insert_axis_inplace(Axis(new_axis));
+                axis_types.push(axis_type);
+            }
+        }
+        let permutation: Vec<usize> = order
+            .iter()
+            .map(|t| axis_types.iter().position(|a| a == t))
+            .collect::<Option<_>>()
+            .ok_or(format!(
+                "{} has repeated image axis types",
+                self.file_path.display()
+            ))?;
+        let data = data
+            .permuted_axes(permutation)
+            .into_dimensionality::<Ix4>()?
+            .as_standard_layout()
+            .into_owned();
+
+        let (num_stokes, num_freqs, num_pixels_y, num_pixels_x) = data.dim();
+        let stokes = axis_values(&mut fptr, &img_hdu, stokes_axis, num_stokes);
+        let freqs = axis_values(&mut fptr, &img_hdu, freq_axis, num_freqs);
+
+        let beam = ["BMAJ", "BMIN", "BPA"]
+            .iter()
+            .map(|key| img_hdu.read_key::<f64>(&mut fptr, key).ok())
+            .collect::<Option<Vec<f64>>>()
+            .and_then(|beam| beam.try_into().ok());
 
         let result = Image {
-            data: data,
-            id: gps_num,
-            num_pixels_x: num_pixels_x,
-            num_pixels_y: num_pixels_y,
+            data,
+            id,
+            num_pixels_x,
+            num_pixels_y,
+            stokes,
+            freqs,
+            wcs,
+            beam,
         };
 
         Ok(result)
     }
+
+    /// Obsid from the OBSID, GPSTIME or DATE-OBS header keys, in that order,
+    /// falling back to the file name.
+    ///
+    /// DATE-OBS is the start of the imaged data rather than of the
+    /// observation, so the obsid from it is approximate: it is a few seconds
+    /// late if the start of the observation was flagged, and later still for
+    /// images of later intervals.
+    fn read_obsid(&self, fptr: &mut FitsFile, hdu: &FitsHdu) -> Result<usize, Box<dyn Error>> {
+        for key in ["OBSID", "GPSTIME"] {
+            if let Ok(id) = hdu.read_key::<i64>(fptr, key)
+                && let Ok(id) = usize::try_from(id)
+            {
+                return Ok(id);
+            }
+        }
+
+        if let Ok(date) = hdu.read_key::<String>(fptr, "DATE-OBS")
+            && let Some(id) = gps_from_utc(&date)
+        {
+            return Ok(id);
+        }
+
+        let name = self
+            .file_path
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default();
+        self.obsid_pattern
+            .captures(name)
+            .and_then(|c| c.get(1).or_else(|| c.get(0)))
+            .and_then(|m| m.as_str().parse().ok())
+            .ok_or_else(|| {
+                format!(
+                    "No obsid in the headers of {} and none matching {} in its name",
+                    self.file_path.display(),
+                    self.obsid_pattern
+                )
+                .into()
+            })
+    }
+}
+
+/// World coordinate of each pixel along FITS axis `axis`, or NaNs if there is
+/// no such axis
+fn axis_values(fptr: &mut FitsFile, hdu: &FitsHdu, axis: Option<usize>, len: usize) -> Vec<f64> {
+    let Some(n) = axis else {
+        return vec![f64::NAN; len];
+    };
+    let mut key = |name: &str, default: f64| {
+        hdu.read_key::<f64>(fptr, &format!("{}{}", name, n))
+            .unwrap_or(default)
+    };
+    let crval = key("CRVAL", f64::NAN);
+    let crpix = key("CRPIX", 1.0);
+    let cdelt = key("CDELT", 1.0);
+
+    // FITS pixels are 1-indexed
+    (0..len)
+        .map(|i| crval + (i as f64 + 1.0 - crpix) * cdelt)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::{MWA_LONGITUDE, ObsTime};
+    use fitsio::images::{ImageDescription, ImageType};
+    use std::path::Path;
+
+    /// Write a blank image at `path` with the given header keys
+    fn write_image(path: &Path, keys: &[(&str, &str)]) {
+        let _ = std::fs::remove_file(path);
+        let description = ImageDescription {
+            data_type: ImageType::Float,
+            dimensions: &[4, 4],
+        };
+        let mut fptr = FitsFile::create(path)
+            .with_custom_primary(&description)
+            .open()
+            .unwrap();
+        let hdu = fptr.primary_hdu().unwrap();
+        hdu.write_image(&mut fptr, &[0.0_f32; 16]).unwrap();
+        for &(key, value) in keys {
+            match value.parse::<i64>() {
+                Ok(value) => hdu.write_key(&mut fptr, key, value).unwrap(),
+                Err(_) => hdu.write_key(&mut fptr, key, value).unwrap(),
+            }
+        }
+    }
+
+    fn read_obsid(name: &str, keys: &[(&str, &str)]) -> Result<usize, Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!(
+            "calmet_image_1090008640_{}_{}.fits",
+            name,
+            std::process::id()
+        ));
+        write_image(&path, keys);
+        let result = ImageFile {
+            file_path: path.clone(),
+            obsid_pattern: Regex::new(r"image_(\d{10})").unwrap(),
+        }
+        .read_fits()
+        .map(|image| image.id);
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn obsid_header_order() {
+        let date = ObsTime::from_gps(1_090_008_648, MWA_LONGITUDE).utc;
+        let all = [
+            ("OBSID", "1090008880"),
+            ("GPSTIME", "1090009120"),
+            ("DATE-OBS", date.as_str()),
+        ];
+        assert_eq!(read_obsid("all", &all).unwrap(), 1_090_008_880);
+        assert_eq!(read_obsid("gpstime", &all[1..]).unwrap(), 1_090_009_120);
+        assert_eq!(read_obsid("date", &all[2..]).unwrap(), 1_090_008_648);
+
+        // Invalid values fall through to the next source
+        let negative = [("OBSID", "-1"), ("GPSTIME", "1090009120")];
+        assert_eq!(read_obsid("negative", &negative).unwrap(), 1_090_009_120);
+        let bad_date = [("DATE-OBS", "unknown")];
+        assert_eq!(read_obsid("bad_date", &bad_date).unwrap(), 1_090_008_640);
+    }
+
+    #[test]
+    fn obsid_from_file_name() {
+        assert_eq!(read_obsid("name", &[]).unwrap(), 1_090_008_640);
+
+        let path =
+            std::env::temp_dir().join(format!("calmet_no_obsid_{}.fits", std::process::id()));
+        write_image(&path, &[]);
+        let error = ImageFile {
+            file_path: path.clone(),
+            obsid_pattern: Regex::new(r"(\d{10})").unwrap(),
+        }
+        .read_fits()
+        .unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+
+        // The error names the file, so a bad file among many can be found
+        assert!(error.to_string().contains(path.to_str().unwrap()));
+    }
 }
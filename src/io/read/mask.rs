@@ -0,0 +1,36 @@
+use fitsio::FitsFile;
+use ndarray::prelude::*;
+use std::error::Error;
+use std::path::PathBuf;
+
+/// Struct for holding path to a FITS mask image with methods for reading.
+/// Non-zero pixels are used and zero or NaN pixels are masked out.
+pub(crate) struct MaskFile {
+    pub(crate) file_path: PathBuf,
+}
+
+impl MaskFile {
+    /// Mask indexed [y, x] like the planes of `Image::data`, true where
+    /// pixels are used
+    pub(crate) fn read(&self) -> Result<Array2<bool>, Box<dyn Error>> {
+        let mut fptr = FitsFile::open(&self.file_path)?;
+        let hdu = fptr.hdu(0)?;
+        let data: ArrayD<f64> = hdu.read_image(&mut fptr)?;
+
+        // Masks cover a single plane, so any other axes must be degenerate
+        let shape: Vec<usize> = data.shape().iter().copied().filter(|&n| n != 1).collect();
+        let [num_y, num_x] = shape[..] else {
+            return Err(format!(
+                "{} has shape {:?} but a mask needs a single plane",
+                self.file_path.display(),
+                data.shape()
+            )
+            .into());
+        };
+
+        let mask = data
+            .into_shape_with_order((num_y, num_x))?
+            .mapv(|v| v.is_finite() && v != 0.0);
+        Ok(mask)
+    }
+}
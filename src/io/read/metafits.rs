@@ -0,0 +1,83 @@
+use fitsio::FitsFile;
+use std::error::Error;
+use std::path::PathBuf;
+
+/// Number of dipoles in an MWA tile
+pub(crate) const NUM_DIPOLES: usize = 16;
+
+/// Delay value the metafits uses to mark a dead dipole
+const DEAD_DIPOLE_DELAY: i32 = 32;
+
+/// Struct for holding the per-tile metadata of an observation
+#[derive(Debug)]
+pub(crate) struct Metafits {
+    // Holds MWA observation ID
+    pub(crate) id: usize,
+
+    // Tile metadata ordered by antenna index, i.e. the same order as the tile
+    // axis of the calibration solutions
+    pub(crate) tiles: Vec<TileInfo>,
+}
+
+/// Metadata for a single tile
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TileInfo {
+    // Name of the tile, e.g. Tile011, HexE1 or LBA1
+    pub(crate) name: String,
+
+    // Receiver the tile is plugged into
+    pub(crate) receiver: usize,
+
+    // Cable type and length class, e.g. RG6_90. Older metafits files don't
+    // have this column.
+    pub(crate) cable_flavour: Option<String>,
+
+    // Number of dead dipoles in the X and Y polarisations
+    pub(crate) dead_dipoles: [usize; 2],
+}
+
+/// Struct for holding path to a metafits file with methods for reading
+pub(crate) struct MetafitsFile {
+    pub(crate) file_path: PathBuf,
+}
+
+impl MetafitsFile {
+    pub(crate) fn read_fits(&self) -> Result<Metafits, Box<dyn Error>> {
+        let mut fptr = FitsFile::open(&self.file_path)?;
+
+        let id: i64 = fptr.hdu(0)?.read_key(&mut fptr, "GPSTIME")?;
+
+        // TILEDATA has one row per input, i.e. two rows (X and Y) per tile
+        let tile_hdu = fptr.hdu("TILEDATA")?;
+        let antennas: Vec<i32> = tile_hdu.read_col(&mut fptr, "Antenna")?;
+        let names: Vec<String> = tile_hdu.read_col(&mut fptr, "TileName")?;
+        let pols: Vec<String> = tile_hdu.read_col(&mut fptr, "Pol")?;
+        let receivers: Vec<i32> = tile_hdu.read_col(&mut fptr, "Rx")?;
+        // 16 dipole delays per row, with dead dipoles given a delay of 32
+        let delays: Vec<i32> = tile_hdu.read_col(&mut fptr, "Delays")?;
+        let flavours: Option<Vec<String>> = tile_hdu.read_col(&mut fptr, "Flavors").ok();
+
+        let num_tiles = antennas.iter().map(|&a| a as usize + 1).max().unwrap_or(0);
+        let mut tiles = vec![TileInfo::default(); num_tiles];
+
+        for (row, &antenna) in antennas.iter().enumerate() {
+            let tile = &mut tiles[antenna as usize];
+            tile.name = names[row].trim().to_string();
+            tile.receiver = receivers[row] as usize;
+            tile.cable_flavour = flavours.as_ref().map(|f| f[row].trim().to_string());
+
+            let pol = if pols[row].trim() == "Y" { 1 } else { 0 };
+            tile.dead_dipoles[pol] = delays[row * NUM_DIPOLES..(row + 1) * NUM_DIPOLES]
+                .iter()
+                .filter(|&&d| d == DEAD_DIPOLE_DELAY)
+                .count();
+        }
+
+        let result = Metafits {
+            id: id as usize,
+            tiles,
+        };
+
+        Ok(result)
+    }
+}
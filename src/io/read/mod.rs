@@ -1,3 +1,9 @@
-use std::path::PathBuf;
+pub(crate) mod catalogue;
+pub(crate) mod history;
 pub(crate) mod image;
+pub(crate) mod mask;
+pub(crate) mod metafits;
+pub(crate) mod region;
+pub(crate) mod results;
 pub(crate) mod solutions;
+pub(crate) mod wcs;
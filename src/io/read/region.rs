@@ -0,0 +1,292 @@
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+/// Coordinate system of a region
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum RegionSystem {
+    /// Pixels, 0-indexed like `Image::data`
+    Image,
+    /// RA and Dec in degrees, with sizes in degrees
+    Sky,
+}
+
+/// Shapes of DS9 regions. Positions are (x, y) in pixels or (RA, Dec) in
+/// degrees depending on the region's system.
+#[derive(Debug, Clone)]
+pub(crate) enum Shape {
+    Circle {
+        centre: (f64, f64),
+        radius: f64,
+    },
+    Box {
+        centre: (f64, f64),
+        width: f64,
+        height: f64,
+        // Rotation anticlockwise from the x axis, i.e. from west towards
+        // north for sky regions [deg]
+        angle: f64,
+    },
+    Polygon(Vec<(f64, f64)>),
+}
+
+/// A single region from a DS9 region file
+#[derive(Debug, Clone)]
+pub(crate) struct Region {
+    pub(crate) shape: Shape,
+    pub(crate) system: RegionSystem,
+
+    // Whether the region was prefixed with `-`, excluding its pixels
+    pub(crate) exclude: bool,
+}
+
+/// Struct for holding path to a DS9 region file with methods for reading.
+///
+/// Circles, boxes and polygons are understood, in image or physical
+/// coordinates, or fk5/icrs sky coordinates. Sky positions can be in degrees
+/// or sexagesimal, and sky sizes in degrees or with a `d`, `'` or `"` unit.
+pub(crate) struct RegionFile {
+    pub(crate) file_path: PathBuf,
+}
+
+impl RegionFile {
+    pub(crate) fn read(&self) -> Result<Vec<Region>, Box<dyn Error>> {
+        let contents = fs::read_to_string(&self.file_path)?;
+
+        // DS9 defaults to physical coordinates, which are image coordinates
+        // for calmet's purposes
+        let mut system = RegionSystem::Image;
+        let mut regions = vec![];
+        for (line_num, line) in contents.lines().enumerate() {
+            let invalid = |reason: &str| {
+                format!(
+                    "Invalid line {} in region file {}: {}",
+                    line_num + 1,
+                    self.file_path.display(),
+                    reason
+                )
+            };
+
+            // Anything after a # is a comment or region properties
+            let line = line.split('#').next().unwrap_or_default();
+            for command in line.split(';').map(str::trim) {
+                if command.is_empty() || command.starts_with("global") {
+                    continue;
+                }
+                match command.to_lowercase().as_str() {
+                    "image" | "physical" => {
+                        system = RegionSystem::Image;
+                        continue;
+                    }
+                    "fk5" | "icrs" | "j2000" => {
+                        system = RegionSystem::Sky;
+                        continue;
+                    }
+                    "fk4" | "b1950" | "galactic" | "ecliptic" | "linear" | "amplifier"
+                    | "detector" => {
+                        return Err(invalid("unsupported coordinate system").into());
+                    }
+                    _ => {}
+                }
+                regions.push(parse_region(command, system).map_err(|e| invalid(&e))?);
+            }
+        }
+
+        Ok(regions)
+    }
+}
+
+/// Parse a shape like `-circle(10:00:00, -27:00:00, 30')`
+fn parse_region(command: &str, system: RegionSystem) -> Result<Region, String> {
+    let (exclude, command) = match command.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, command.strip_prefix('+').unwrap_or(command)),
+    };
+    let (name, rest) = command
+        .split_once('(')
+        .ok_or_else(|| format!("no arguments in {}", command))?;
+    let args: Vec<&str> = rest
+        .split(')')
+        .next()
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .collect();
+
+    let position = |i: usize| -> Result<(f64, f64), String> {
+        let (x, y) = (arg(&args, i)?, arg(&args, i + 1)?);
+        match system {
+            // DS9 image pixels are 1-indexed
+            RegionSystem::Image => Ok((parse_number(x)? - 1.0, parse_number(y)? - 1.0)),
+            RegionSystem::Sky => Ok((parse_angle(x, true)?, parse_angle(y, false)?)),
+        }
+    };
+    let size = |i: usize| -> Result<f64, String> {
+        let size = arg(&args, i)?;
+        match system {
+            RegionSystem::Image => parse_number(size),
+            RegionSystem::Sky => parse_size(size),
+        }
+    };
+
+    let shape = match name.trim().to_lowercase().as_str() {
+        "circle" => Shape::Circle {
+            centre: position(0)?,
+            radius: size(2)?,
+        },
+        "box" => Shape::Box {
+            centre: position(0)?,
+            width: size(2)?,
+            height: size(3)?,
+            angle: args.get(4).map_or(Ok(0.0), |a| parse_number(a))?,
+        },
+        "polygon" => {
+            if args.len() < 6 || !args.len().is_multiple_of(2) {
+                return Err("a polygon needs at least 3 vertices".to_string());
+            }
+            Shape::Polygon(
+                (0..args.len())
+                    .step_by(2)
+                    .map(position)
+                    .collect::<Result<_, _>>()?,
+            )
+        }
+        other => return Err(format!("unsupported region shape {}", other)),
+    };
+
+    Ok(Region {
+        shape,
+        system,
+        exclude,
+    })
+}
+
+fn arg<'a>(args: &[&'a str], i: usize) -> Result<&'a str, String> {
+    args.get(i)
+        .copied()
+        .filter(|a| !a.is_empty())
+        .ok_or_else(|| format!("missing argument {}", i + 1))
+}
+
+fn parse_number(value: &str) -> Result<f64, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} is not a number", value))
+}
+
+/// Sky position in degrees, from degrees or sexagesimal hours (RA) or degrees
+/// (Dec)
+fn parse_angle(value: &str, is_ra: bool) -> Result<f64, String> {
+    let value = value.trim_end_matches('d');
+    if !value.contains([':', 'h', 'm', 's']) {
+        return parse_number(value);
+    }
+
+    let negative = value.starts_with('-');
+    let parts: Vec<f64> = value
+        .trim_start_matches(['-', '+'])
+        .split([':', 'h', 'd', 'm', 's'])
+        .filter(|p| !p.is_empty())
+        .map(parse_number)
+        .collect::<Result<_, _>>()?;
+    let magnitude = parts
+        .iter()
+        .zip([1.0, 60.0, 3600.0])
+        .map(|(p, scale)| p / scale)
+        .sum::<f64>();
+    let degrees = if is_ra { magnitude * 15.0 } else { magnitude };
+    Ok(if negative { -degrees } else { degrees })
+}
+
+/// Sky size in degrees, from degrees or arcmin (') or arcsec (")
+fn parse_size(value: &str) -> Result<f64, String> {
+    if let Some(arcsec) = value.strip_suffix('"') {
+        Ok(parse_number(arcsec)? / 3600.0)
+    } else if let Some(arcmin) = value.strip_suffix('\'') {
+        Ok(parse_number(arcmin)? / 60.0)
+    } else {
+        parse_number(value.trim_end_matches('d'))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn image_circle_is_zero_indexed() {
+        let region = parse_region("circle(65, 65, 10)", RegionSystem::Image).unwrap();
+        assert!(!region.exclude);
+        assert_eq!(region.system, RegionSystem::Image);
+        let Shape::Circle { centre, radius } = region.shape else {
+            panic!("expected a circle, got {:?}", region.shape);
+        };
+        assert_eq!(centre, (64.0, 64.0));
+        assert_eq!(radius, 10.0);
+    }
+
+    #[test]
+    fn sexagesimal_sky_circle() {
+        let region = parse_region("-circle(0:40:00, -27:30:00, 30')", RegionSystem::Sky).unwrap();
+        assert!(region.exclude);
+        let Shape::Circle { centre, radius } = region.shape else {
+            panic!("expected a circle, got {:?}", region.shape);
+        };
+        assert_close(centre.0, 10.0);
+        assert_close(centre.1, -27.5);
+        assert_close(radius, 0.5);
+    }
+
+    #[test]
+    fn sky_box_with_angle_and_units() {
+        let region = parse_region("box(10d, -27, 36\", 0.1d, 45)", RegionSystem::Sky).unwrap();
+        let Shape::Box {
+            centre,
+            width,
+            height,
+            angle,
+        } = region.shape
+        else {
+            panic!("expected a box, got {:?}", region.shape);
+        };
+        assert_eq!(centre, (10.0, -27.0));
+        assert_close(width, 0.01);
+        assert_close(height, 0.1);
+        assert_eq!(angle, 45.0);
+    }
+
+    #[test]
+    fn polygon_vertices() {
+        let region = parse_region("+polygon(1, 1, 11, 1, 6, 9)", RegionSystem::Image).unwrap();
+        let Shape::Polygon(vertices) = region.shape else {
+            panic!("expected a polygon, got {:?}", region.shape);
+        };
+        assert_eq!(vertices, vec![(0.0, 0.0), (10.0, 0.0), (5.0, 8.0)]);
+    }
+
+    #[test]
+    fn invalid_regions() {
+        for command in [
+            "circle(10, 20)",
+            "circle 10 20 5",
+            "ellipse(10, 20, 5, 3, 0)",
+            "polygon(1, 1, 2, 2)",
+            "box(1, 2, x, 4)",
+        ] {
+            assert!(
+                parse_region(command, RegionSystem::Image).is_err(),
+                "{} parsed",
+                command
+            );
+        }
+    }
+}
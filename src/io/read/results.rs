@@ -0,0 +1,55 @@
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+/// Number of time columns written after the obsid in results files
+const NUM_TIME_COLUMNS: usize = 3;
+
+/// Obsids with the values of each row
+type ResultsRows = (Vec<usize>, Vec<Vec<f64>>);
+
+/// Struct for holding path to one of calmet's own results files, i.e. one
+/// written by `write_results` or `write_results_1d`, with methods for reading
+pub(crate) struct ResultsFile {
+    pub(crate) file_path: PathBuf,
+}
+
+impl ResultsFile {
+    /// Read the obsids and the values of each row, skipping comment lines and
+    /// the time columns
+    pub(crate) fn read(&self) -> Result<ResultsRows, Box<dyn Error>> {
+        let contents = fs::read_to_string(&self.file_path)?;
+
+        let mut obsids = vec![];
+        let mut rows = vec![];
+        for (line_num, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || {
+                format!(
+                    "Invalid line {} in results file {}",
+                    line_num + 1,
+                    self.file_path.display()
+                )
+            };
+            let mut columns = line.split_whitespace();
+            let obsid: usize = columns
+                .next()
+                .and_then(|c| c.parse().ok())
+                .ok_or_else(invalid)?;
+            let values = columns
+                .skip(NUM_TIME_COLUMNS)
+                .map(|c| c.parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid())?;
+
+            obsids.push(obsid);
+            rows.push(values);
+        }
+
+        Ok((obsids, rows))
+    }
+}
@@ -1,9 +1,16 @@
 use fitsio::FitsFile;
+use itertools::Itertools;
 use ndarray::{Zip, prelude::*};
 use num_complex::Complex64;
 use std::error::Error;
 use std::path::PathBuf;
 
+/// Width of an MWA coarse channel [Hz]
+pub(crate) const COARSE_CHAN_WIDTH: f64 = 1.28e6;
+
+/// Number of coarse channels in an MWA observation
+pub(crate) const NUM_COARSE_CHANS: usize = 24;
+
 /// Struct for holding information about the calibration solutions
 #[derive(Debug)]
 pub(crate) struct Solutions {
@@ -18,6 +25,12 @@ pub(crate) struct Solutions {
 
     // Number of frequency channels
     pub(crate) num_chans: usize,
+
+    // Centre frequency of each channel [Hz], if the file has a CHANBLOCKS HDU
+    pub(crate) chan_freqs: Option<Array1<f64>>,
+
+    // Name of each tile, if the file has a TILES HDU
+    pub(crate) tile_names: Option<Vec<String>>,
 }
 
 /// Struct for holding path to calibration solutions with methods for reading
@@ -25,8 +38,54 @@ pub(crate) struct CalSolFile {
     pub(crate) file_path: PathBuf,
 }
 
+impl Solutions {
+    /// Coarse channel of each fine channel, numbered in frequency order from 0
+    pub(crate) fn coarse_chans(&self) -> Vec<usize> {
+        match &self.chan_freqs {
+            Some(freqs) => {
+                // MWA coarse channel n is centred on n * 1.28 MHz
+                let receiver_chans = freqs.mapv(|f| (f / COARSE_CHAN_WIDTH).round() as usize);
+                let unique_chans: Vec<usize> =
+                    receiver_chans.iter().copied().sorted().dedup().collect();
+                receiver_chans
+                    .iter()
+                    .map(|c| unique_chans.binary_search(c).unwrap())
+                    .collect()
+            }
+            None => {
+                let chans_per_coarse = self.num_chans.div_ceil(NUM_COARSE_CHANS).max(1);
+                (0..self.num_chans).map(|c| c / chans_per_coarse).collect()
+            }
+        }
+    }
+
+    /// Index in these solutions of each tile of `target`. Tiles are matched
+    /// by name when both have tile names, as the array configuration and so
+    /// the tile order can change between observations, otherwise by index.
+    pub(crate) fn tile_map(&self, target: &Solutions) -> Vec<Option<usize>> {
+        match (&self.tile_names, &target.tile_names) {
+            (Some(names), Some(target_names)) => target_names
+                .iter()
+                .map(|t| names.iter().position(|n| n == t))
+                .collect(),
+            _ => (0..target.num_tiles)
+                .map(|t| (t < self.num_tiles).then_some(t))
+                .collect(),
+        }
+    }
+}
+
 impl CalSolFile {
+    /// Solutions of the first timeblock
     pub(crate) fn read_fits(&self) -> Result<Solutions, Box<dyn Error>> {
+        self.read_timeblocks()?
+            .into_iter()
+            .next()
+            .ok_or_else(|| format!("No timeblocks in {}", self.file_path.display()).into())
+    }
+
+    /// Solutions of every timeblock, in the order they are in the file
+    pub(crate) fn read_timeblocks(&self) -> Result<Vec<Solutions>, Box<dyn Error>> {
         let mut fptr = FitsFile::open(&self.file_path)?;
 
         let sol_hdu = fptr.hdu(1)?;
@@ -44,16 +103,31 @@ impl CalSolFile {
             .and(imag_view)
             .map_collect(|&real, &imag| Complex64::new(real, imag));
 
-        let complex_gains = complex_array.remove_axis(Axis(0));
-
         let id: i64 = fptr.hdu(0)?.read_key(&mut fptr, "OBSID")?;
 
-        let result = Solutions {
-            complex_gains: complex_gains,
-            id: id as usize,
-            num_tiles: num_tiles,
-            num_chans: num_chans,
-        };
+        let chan_freqs = fptr
+            .hdu("CHANBLOCKS")
+            .and_then(|hdu| hdu.read_col::<f64>(&mut fptr, "Freq"))
+            .ok()
+            .map(Array1::from);
+
+        let tile_names: Option<Vec<String>> = fptr
+            .hdu("TILES")
+            .and_then(|hdu| hdu.read_col::<String>(&mut fptr, "TileName"))
+            .ok()
+            .map(|names| names.iter().map(|n| n.trim().to_string()).collect());
+
+        let result = complex_array
+            .outer_iter()
+            .map(|complex_gains| Solutions {
+                complex_gains: complex_gains.to_owned(),
+                id: id as usize,
+                num_tiles,
+                num_chans,
+                chan_freqs: chan_freqs.clone(),
+                tile_names: tile_names.clone(),
+            })
+            .collect();
 
         Ok(result)
     }
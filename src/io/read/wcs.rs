@@ -0,0 +1,417 @@
+use fitsio::FitsFile;
+use fitsio::hdu::FitsHdu;
+use std::error::Error;
+
+/// Celestial projections understood by `Wcs`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Projection {
+    /// Slant orthographic, the usual projection of WSClean images
+    Sin,
+    /// Gnomonic
+    Tan,
+    /// Zenithal equal area
+    Zea,
+    /// Plate carrée
+    Car,
+}
+
+/// Celestial world coordinate system of an image, mapping pixels to RA and
+/// Dec following FITS WCS papers I and II. Angles are in degrees and pixels
+/// are 0-indexed along x (RA) and y (Dec).
+#[derive(Debug, Clone)]
+pub(crate) struct Wcs {
+    pub(crate) projection: Projection,
+
+    // CTYPE of the RA and Dec axes
+    ctype: [String; 2],
+
+    // Reference pixel, 0-indexed
+    crpix: [f64; 2],
+
+    // Linear transformation from pixel offsets to intermediate world
+    // coordinates [deg], combining PC and CDELT if there's no CD matrix
+    cd: [[f64; 2]; 2],
+
+    // Inverse of `cd`
+    cd_inv: [[f64; 2]; 2],
+
+    // RA and Dec of the reference pixel, i.e. the phase centre for WSClean
+    pub(crate) crval: [f64; 2],
+
+    // LONPOLE and LATPOLE, kept to write the WCS out again
+    lonpole: f64,
+    latpole: f64,
+
+    // Celestial longitude and latitude of the native pole, and the native
+    // longitude of the celestial pole
+    pole: [f64; 3],
+}
+
+impl Wcs {
+    /// Read the WCS of the celestial axes `lon_axis` and `lat_axis`, numbered
+    /// from 1 as in the FITS keys
+    pub(crate) fn read(
+        fptr: &mut FitsFile,
+        hdu: &FitsHdu,
+        lon_axis: usize,
+        lat_axis: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        let ctype: String = hdu.read_key(fptr, &format!("CTYPE{}", lon_axis))?;
+        let lat_ctype: String = hdu.read_key(fptr, &format!("CTYPE{}", lat_axis))?;
+        let projection = match ctype.trim().get(5..8) {
+            Some("SIN") => Projection::Sin,
+            Some("TAN") => Projection::Tan,
+            Some("ZEA") => Projection::Zea,
+            Some("CAR") => Projection::Car,
+            _ => return Err(format!("Unsupported celestial projection {}", ctype).into()),
+        };
+        if lat_ctype.trim().get(5..8) != ctype.trim().get(5..8) {
+            return Err(format!(
+                "Celestial axes {} and {} have different projections",
+                ctype.trim(),
+                lat_ctype.trim()
+            )
+            .into());
+        }
+
+        let mut key = |name: String| hdu.read_key::<f64>(fptr, &name).ok();
+        let axes = [lon_axis, lat_axis];
+
+        let crpix = axes.map(|a| key(format!("CRPIX{}", a)).unwrap_or(1.0) - 1.0);
+        let crval = axes.map(|a| key(format!("CRVAL{}", a)).unwrap_or(0.0));
+
+        let cd_keys = axes.map(|i| axes.map(|j| key(format!("CD{}_{}", i, j))));
+        let cd = if cd_keys.iter().flatten().any(Option::is_some) {
+            cd_keys.map(|row| row.map(|v| v.unwrap_or(0.0)))
+        } else {
+            let cdelt = axes.map(|a| key(format!("CDELT{}", a)).unwrap_or(1.0));
+            let identity = |i: usize, j: usize| if i == j { 1.0 } else { 0.0 };
+            let mut pc = [[0.0; 2]; 2];
+            for (i, &axis_i) in axes.iter().enumerate() {
+                for (j, &axis_j) in axes.iter().enumerate() {
+                    pc[i][j] = key(format!("PC{}_{}", axis_i, axis_j)).unwrap_or(identity(i, j));
+                }
+            }
+            // Older images rotate with CROTA2 instead of PC
+            if let Some(crota) = key(format!("CROTA{}", lat_axis)) {
+                let (sin, cos) = crota.to_radians().sin_cos();
+                let ratio = cdelt[1] / cdelt[0];
+                pc = [[cos, -sin * ratio], [sin / ratio, cos]];
+            }
+            [0, 1].map(|i| [0, 1].map(|j| cdelt[i] * pc[i][j]))
+        };
+
+        let det = cd[0][0] * cd[1][1] - cd[0][1] * cd[1][0];
+        if det == 0.0 {
+            return Err("Singular WCS transformation matrix".into());
+        }
+        let cd_inv = [
+            [cd[1][1] / det, -cd[0][1] / det],
+            [-cd[1][0] / det, cd[0][0] / det],
+        ];
+
+        let native_ref = match projection {
+            Projection::Sin | Projection::Tan | Projection::Zea => [0.0, 90.0],
+            Projection::Car => [0.0, 0.0],
+        };
+        let default_lonpole = if crval[1] >= native_ref[1] {
+            0.0
+        } else {
+            180.0
+        };
+        let lonpole = key("LONPOLE".to_string()).unwrap_or(default_lonpole);
+        let latpole = key("LATPOLE".to_string()).unwrap_or(90.0);
+        let pole = celestial_pole(crval, native_ref, lonpole, latpole);
+
+        Ok(Self {
+            projection,
+            ctype: [ctype.trim().to_string(), lat_ctype.trim().to_string()],
+            crpix,
+            cd,
+            cd_inv,
+            crval,
+            lonpole,
+            latpole,
+            pole,
+        })
+    }
+
+    /// Solid angle of a pixel at the reference point [deg^2]
+    pub(crate) fn pixel_area(&self) -> f64 {
+        (self.cd[0][0] * self.cd[1][1] - self.cd[0][1] * self.cd[1][0]).abs()
+    }
+
+    /// Write the WCS as FITS axes 1 (RA) and 2 (Dec), using a CD matrix
+    pub(crate) fn write(&self, fptr: &mut FitsFile, hdu: &FitsHdu) -> Result<(), Box<dyn Error>> {
+        for (i, ctype) in self.ctype.iter().enumerate() {
+            hdu.write_key(fptr, &format!("CTYPE{}", i + 1), ctype.as_str())?;
+            hdu.write_key(fptr, &format!("CRPIX{}", i + 1), self.crpix[i] + 1.0)?;
+            hdu.write_key(fptr, &format!("CRVAL{}", i + 1), self.crval[i])?;
+            for j in 0..2 {
+                hdu.write_key(fptr, &format!("CD{}_{}", i + 1, j + 1), self.cd[i][j])?;
+            }
+        }
+        hdu.write_key(fptr, "LONPOLE", self.lonpole)?;
+        hdu.write_key(fptr, "LATPOLE", self.latpole)?;
+        Ok(())
+    }
+
+    /// RA and Dec [deg] of a pixel, None if it's off the projection
+    pub(crate) fn pixel_to_world(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        let (dx, dy) = (x - self.crpix[0], y - self.crpix[1]);
+        let u = self.cd[0][0] * dx + self.cd[0][1] * dy;
+        let v = self.cd[1][0] * dx + self.cd[1][1] * dy;
+
+        let (phi, theta) = match self.projection {
+            Projection::Sin | Projection::Tan | Projection::Zea => {
+                let r = u.hypot(v).to_radians();
+                let theta = match self.projection {
+                    Projection::Sin if r <= 1.0 => r.acos(),
+                    Projection::Tan => (1.0 / r).atan(),
+                    Projection::Zea if r <= 2.0 => {
+                        std::f64::consts::FRAC_PI_2 - 2.0 * (r / 2.0).asin()
+                    }
+                    _ => return None,
+                };
+                (u.atan2(-v), theta)
+            }
+            Projection::Car => (u.to_radians(), v.to_radians()),
+        };
+
+        let [ra_pole, dec_pole, phi_pole] = self.pole.map(f64::to_radians);
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let (sin_dp, cos_dp) = dec_pole.sin_cos();
+        let dphi = phi - phi_pole;
+
+        let ra = ra_pole
+            + (-cos_theta * dphi.sin()).atan2(sin_theta * cos_dp - cos_theta * sin_dp * dphi.cos());
+        let dec = (sin_theta * sin_dp + cos_theta * cos_dp * dphi.cos())
+            .clamp(-1.0, 1.0)
+            .asin();
+
+        Some((ra.to_degrees().rem_euclid(360.0), dec.to_degrees()))
+    }
+
+    /// Pixel of an RA and Dec [deg], None if it's off the projection
+    pub(crate) fn world_to_pixel(&self, ra: f64, dec: f64) -> Option<(f64, f64)> {
+        let [ra_pole, dec_pole, phi_pole] = self.pole.map(f64::to_radians);
+        let (ra, dec) = (ra.to_radians(), dec.to_radians());
+        let (sin_dec, cos_dec) = dec.sin_cos();
+        let (sin_dp, cos_dp) = dec_pole.sin_cos();
+        let dra = ra - ra_pole;
+
+        let phi = phi_pole
+            + (-cos_dec * dra.sin()).atan2(sin_dec * cos_dp - cos_dec * sin_dp * dra.cos());
+        let theta = (sin_dec * sin_dp + cos_dec * cos_dp * dra.cos())
+            .clamp(-1.0, 1.0)
+            .asin();
+
+        let (u, v) = match self.projection {
+            Projection::Sin | Projection::Tan | Projection::Zea => {
+                // SIN and TAN only show the hemisphere facing the reference
+                // point
+                if self.projection != Projection::Zea && theta <= 0.0 {
+                    return None;
+                }
+                let r = match self.projection {
+                    Projection::Sin => theta.cos(),
+                    Projection::Tan => 1.0 / theta.tan(),
+                    _ => 2.0 * ((std::f64::consts::FRAC_PI_2 - theta) / 2.0).sin(),
+                }
+                .to_degrees();
+                (r * phi.sin(), -r * phi.cos())
+            }
+            Projection::Car => {
+                let phi = (phi.to_degrees() + 180.0).rem_euclid(360.0) - 180.0;
+                (phi, theta.to_degrees())
+            }
+        };
+
+        let x = self.crpix[0] + self.cd_inv[0][0] * u + self.cd_inv[0][1] * v;
+        let y = self.crpix[1] + self.cd_inv[1][0] * u + self.cd_inv[1][1] * v;
+        Some((x, y))
+    }
+}
+
+/// Angular distance between two positions [deg]
+pub(crate) fn angular_distance(ra1: f64, dec1: f64, ra2: f64, dec2: f64) -> f64 {
+    let (ra1, dec1, ra2, dec2) = (
+        ra1.to_radians(),
+        dec1.to_radians(),
+        ra2.to_radians(),
+        dec2.to_radians(),
+    );
+    // Haversine, which stays accurate for small separations
+    let h = ((dec2 - dec1) / 2.0).sin().powi(2)
+        + dec1.cos() * dec2.cos() * ((ra2 - ra1) / 2.0).sin().powi(2);
+    (2.0 * h.sqrt().clamp(0.0, 1.0).asin()).to_degrees()
+}
+
+/// Celestial longitude and latitude of the native pole and native longitude
+/// of the celestial pole [deg], from the reference point's celestial and
+/// native coordinates (WCS paper II, section 2.4)
+fn celestial_pole(crval: [f64; 2], native_ref: [f64; 2], lonpole: f64, latpole: f64) -> [f64; 3] {
+    let [ra0, dec0] = crval.map(f64::to_radians);
+    let [phi0, theta0] = native_ref.map(f64::to_radians);
+    let phi_p = lonpole.to_radians();
+
+    // Zenithal projections have the native pole at the reference point
+    if native_ref[1] == 90.0 {
+        return [crval[0], crval[1], lonpole];
+    }
+
+    let dphi = phi_p - phi0;
+    let base = theta0.sin().atan2(theta0.cos() * dphi.cos());
+    let spread = (dec0.sin() / (1.0 - (theta0.cos() * dphi.sin()).powi(2)).sqrt())
+        .clamp(-1.0, 1.0)
+        .acos();
+    let candidates = [base + spread, base - spread];
+    let valid = |d: &&f64| d.abs() <= std::f64::consts::FRAC_PI_2 + 1e-12;
+    let dec_p = candidates
+        .iter()
+        .filter(valid)
+        .min_by(|a, b| {
+            (a.to_degrees() - latpole)
+                .abs()
+                .total_cmp(&(b.to_degrees() - latpole).abs())
+        })
+        .copied()
+        .unwrap_or(candidates[0]);
+
+    let ra_p = if dec0.cos().abs() < 1e-12 {
+        ra0
+    } else if dec_p.cos().abs() < 1e-12 {
+        // The native and celestial poles coincide
+        if dec_p > 0.0 {
+            ra0 + dphi - std::f64::consts::PI
+        } else {
+            ra0 - dphi
+        }
+    } else {
+        ra0 - (dphi.sin() * theta0.cos() / dec0.cos())
+            .atan2((theta0.sin() - dec_p.sin() * dec0.sin()) / (dec_p.cos() * dec0.cos()))
+    };
+
+    [ra_p.to_degrees(), dec_p.to_degrees(), lonpole]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fitsio::images::{ImageDescription, ImageType};
+
+    /// WCS read back from a temporary image with the header of a WSClean
+    /// image: RA and Dec axes, then frequency and Stokes
+    fn wcs_from_header(name: &str, projection: &str, crval: [f64; 2], cdelt: f64) -> Wcs {
+        read_header(name, [projection, projection], crval, cdelt).unwrap()
+    }
+
+    /// As `wcs_from_header`, with separate RA and Dec projections
+    fn read_header(
+        name: &str,
+        projections: [&str; 2],
+        crval: [f64; 2],
+        cdelt: f64,
+    ) -> Result<Wcs, Box<dyn Error>> {
+        let path =
+            std::env::temp_dir().join(format!("calmet_wcs_{}_{}.fits", name, std::process::id()));
+        let mut fptr = FitsFile::create(&path).overwrite().open().unwrap();
+        let description = ImageDescription {
+            data_type: ImageType::Float,
+            dimensions: &[1, 1, 128, 128],
+        };
+        let hdu = fptr.create_image("", &description).unwrap();
+        hdu.write_key(&mut fptr, "CTYPE1", format!("RA---{}", projections[0]))
+            .unwrap();
+        hdu.write_key(&mut fptr, "CTYPE2", format!("DEC--{}", projections[1]))
+            .unwrap();
+        for (key, value) in [
+            ("CRPIX1", 65.0),
+            ("CRPIX2", 65.0),
+            ("CRVAL1", crval[0]),
+            ("CRVAL2", crval[1]),
+            ("CDELT1", -cdelt),
+            ("CDELT2", cdelt),
+        ] {
+            hdu.write_key(&mut fptr, key, value).unwrap();
+        }
+        hdu.write_key(&mut fptr, "CTYPE3", "FREQ").unwrap();
+        hdu.write_key(&mut fptr, "CTYPE4", "STOKES").unwrap();
+
+        let wcs = Wcs::read(&mut fptr, &hdu, 1, 2);
+        std::fs::remove_file(&path).unwrap();
+        wcs
+    }
+
+    fn assert_close(actual: (f64, f64), expected: (f64, f64), tolerance: f64) {
+        assert!(
+            (actual.0 - expected.0).abs() < tolerance && (actual.1 - expected.1).abs() < tolerance,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn wsclean_sin_header() {
+        let wcs = wcs_from_header("wsclean", "SIN", [10.0, -27.0], 0.02);
+        assert_eq!(wcs.projection, Projection::Sin);
+        assert_close(
+            wcs.pixel_to_world(64.0, 64.0).unwrap(),
+            (10.0, -27.0),
+            1e-12,
+        );
+        assert_close(
+            wcs.pixel_to_world(90.0, 30.0).unwrap(),
+            (9.412794314553132, -27.67880614094566),
+            1e-9,
+        );
+        assert_close(
+            wcs.pixel_to_world(20.0, 100.0).unwrap(),
+            (10.981459586114173, -26.276558936326506),
+            1e-9,
+        );
+        assert!((wcs.pixel_area() - 0.02 * 0.02).abs() < 1e-15);
+    }
+
+    #[test]
+    fn known_positions_of_each_projection() {
+        for (projection, crval, expected) in [
+            ("SIN", [10.0, -27.0], (351.8422382096, -43.2748775295)),
+            ("ZEA", [10.0, -27.0], (352.2542641320, -43.0030241018)),
+            ("TAN", [10.0, -27.0], (353.3006617038, -42.2948966402)),
+            ("CAR", [10.0, 0.0], (357.0, -17.0)),
+        ] {
+            let wcs = wcs_from_header(&format!("known_{}", projection), projection, crval, 0.5);
+            assert_close(wcs.pixel_to_world(90.0, 30.0).unwrap(), expected, 1e-9);
+        }
+    }
+
+    #[test]
+    fn pixel_world_pixel_round_trip() {
+        for projection in ["SIN", "TAN", "ZEA", "CAR"] {
+            let wcs = wcs_from_header(
+                &format!("round_trip_{}", projection),
+                projection,
+                [10.0, -27.0],
+                0.02,
+            );
+            for (x, y) in [(0.0, 0.0), (64.0, 64.0), (90.0, 30.0), (127.0, 5.5)] {
+                let (ra, dec) = wcs.pixel_to_world(x, y).unwrap();
+                assert_close(wcs.world_to_pixel(ra, dec).unwrap(), (x, y), 1e-8);
+            }
+        }
+    }
+
+    #[test]
+    fn angular_distance_along_meridian_and_equator() {
+        assert!((angular_distance(10.0, -27.0, 10.0, -26.0) - 1.0).abs() < 1e-12);
+        assert!((angular_distance(359.5, 0.0, 0.5, 0.0) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn mismatched_projections() {
+        assert!(read_header("mismatched", ["SIN", "TAN"], [10.0, -27.0], 0.02).is_err());
+        assert!(read_header("unsupported", ["AIT", "AIT"], [10.0, -27.0], 0.02).is_err());
+    }
+}
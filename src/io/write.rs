@@ -1,3 +1,17 @@
+use crate::io::read::history::History;
+use crate::io::read::image::Image;
+use crate::io::read::solutions::Solutions;
+use crate::metrics::gain_amplitude::RatioFlag;
+use crate::metrics::grouping::GroupStats;
+use crate::metrics::jumps::Jump;
+use crate::metrics::sources::Source;
+use crate::metrics::summary::{MetricSummary, Summary};
+use crate::time::{OBSID_COLUMNS, obsid_columns};
+use fitsio::FitsFile;
+use fitsio::images::{ImageDescription, ImageType};
+use fitsio::tables::{ColumnDataType, ColumnDescription};
+use ndarray::{Array4, Axis};
+use std::error::Error;
 use std::fs;
 use std::io::{BufWriter, Write};
 use std::path::Path;
@@ -5,6 +19,20 @@ use std::path::Path;
 pub(crate) fn write_results(
     path: &Path,
     obsids: &[usize],
+    longitude: f64,
+    results: &mut [Vec<f64>],
+) -> std::io::Result<()> {
+    let columns = format!("{}, then the values", OBSID_COLUMNS);
+    write_results_with_header(path, &[&columns], obsids, longitude, results)
+}
+
+/// Same as `write_results`, starting the file with `header` as comment lines.
+/// Each row starts with the obsid and its time columns.
+pub(crate) fn write_results_with_header(
+    path: &Path,
+    header: &[&str],
+    obsids: &[usize],
+    longitude: f64,
     results: &mut [Vec<f64>],
 ) -> std::io::Result<()> {
     if let Some(parent_dir) = path.parent() {
@@ -14,6 +42,10 @@ pub(crate) fn write_results(
     let file = fs::File::create(path)?;
     let mut writer = BufWriter::new(file);
 
+    for line in header {
+        writeln!(writer, "# {}", line)?;
+    }
+
     let max_len = results.iter().map(|v| v.len()).max().unwrap_or(0);
 
     for (obsid, data) in obsids.iter().zip(results.iter_mut()) {
@@ -23,15 +55,16 @@ pub(crate) fn write_results(
             .map(|&val| format!("{:.10}", val))
             .collect::<Vec<String>>()
             .join(" ");
-        writeln!(writer, "{} {}", obsid, line)?;
+        writeln!(writer, "{} {}", obsid_columns(*obsid, longitude), line)?;
     }
 
     Ok(())
 }
 
-pub(crate) fn write_results_1D(
+pub(crate) fn write_results_1d(
     path: &Path,
     obsids: &[usize],
+    longitude: f64,
     results: &[f64],
 ) -> std::io::Result<()> {
     if let Some(parent_dir) = path.parent() {
@@ -41,9 +74,593 @@ pub(crate) fn write_results_1D(
     let file = fs::File::create(path)?;
     let mut writer = BufWriter::new(file);
 
+    writeln!(writer, "# {} value", OBSID_COLUMNS)?;
     for (obsid, result) in obsids.iter().zip(results.iter()) {
-        writeln!(writer, "{} {}", obsid, result)?;
+        writeln!(writer, "{} {}", obsid_columns(*obsid, longitude), result)?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn write_group_results(
+    path: &Path,
+    obsids: &[usize],
+    longitude: f64,
+    results: &[Vec<GroupStats>],
+) -> std::io::Result<()> {
+    if let Some(parent_dir) = path.parent() {
+        fs::create_dir_all(parent_dir)?;
+    }
+
+    let file = fs::File::create(path)?;
+    let mut writer = BufWriter::new(file);
+
+    writeln!(
+        writer,
+        "# {} group num_tiles mean median worst",
+        OBSID_COLUMNS
+    )?;
+    for (obsid, groups) in obsids.iter().zip(results.iter()) {
+        for group in groups {
+            writeln!(
+                writer,
+                "{} {} {} {:.10} {:.10} {:.10}",
+                obsid_columns(*obsid, longitude),
+                group.label,
+                group.num_tiles,
+                group.mean,
+                group.median,
+                group.worst
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn write_ratio_flags(
+    path: &Path,
+    obsids: &[usize],
+    longitude: f64,
+    results: &[Vec<RatioFlag>],
+) -> std::io::Result<()> {
+    if let Some(parent_dir) = path.parent() {
+        fs::create_dir_all(parent_dir)?;
+    }
+
+    let file = fs::File::create(path)?;
+    let mut writer = BufWriter::new(file);
+
+    writeln!(
+        writer,
+        "# {} tile ratio dead_x dead_y expected_ratio status",
+        OBSID_COLUMNS
+    )?;
+    for (obsid, flags) in obsids.iter().zip(results.iter()) {
+        for flag in flags {
+            let (dead_x, dead_y) = match flag.dead_dipoles {
+                Some([x, y]) => (x.to_string(), y.to_string()),
+                None => ("NaN".to_string(), "NaN".to_string()),
+            };
+            writeln!(
+                writer,
+                "{} {} {:.10} {} {} {:.10} {}",
+                obsid_columns(*obsid, longitude),
+                flag.tile,
+                flag.ratio,
+                dead_x,
+                dead_y,
+                flag.expected_ratio.unwrap_or(f64::NAN),
+                flag.status
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn write_jumps(
+    path: &Path,
+    obsids: &[usize],
+    longitude: f64,
+    results: &[Vec<Jump>],
+) -> std::io::Result<()> {
+    if let Some(parent_dir) = path.parent() {
+        fs::create_dir_all(parent_dir)?;
+    }
+
+    let file = fs::File::create(path)?;
+    let mut writer = BufWriter::new(file);
+
+    writeln!(
+        writer,
+        "# {} tile pol chan freq step significance",
+        OBSID_COLUMNS
+    )?;
+    for (obsid, jumps) in obsids.iter().zip(results.iter()) {
+        for jump in jumps {
+            writeln!(
+                writer,
+                "{} {} {} {} {:.1} {:.10} {:.10}",
+                obsid_columns(*obsid, longitude),
+                jump.tile,
+                jump.pol,
+                jump.chan,
+                jump.freq,
+                jump.step,
+                jump.significance
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write per-tile values ranked from largest to smallest, NaNs last
+pub(crate) fn write_tile_ranking(path: &Path, header: &str, values: &[f64]) -> std::io::Result<()> {
+    if let Some(parent_dir) = path.parent() {
+        fs::create_dir_all(parent_dir)?;
+    }
+
+    let file = fs::File::create(path)?;
+    let mut writer = BufWriter::new(file);
+
+    let mut tiles: Vec<usize> = (0..values.len()).collect();
+    tiles.sort_by(|&a, &b| {
+        values[a]
+            .is_nan()
+            .cmp(&values[b].is_nan())
+            .then(values[b].total_cmp(&values[a]))
+    });
+
+    writeln!(writer, "# rank tile {}", header)?;
+    for (rank, tile) in tiles.iter().enumerate() {
+        writeln!(writer, "{} {} {:.10}", rank + 1, tile, values[*tile])?;
+    }
+
+    Ok(())
+}
+
+/// Write solutions in the hyperdrive FITS format: the gains in a SOLUTIONS
+/// image with a single timeblock and, if known, the tile names in a TILES
+/// table and the channel frequencies in a CHANBLOCKS table. Tiles and
+/// channels without any gains are flagged in those tables. `keys` are added
+/// to the primary header alongside OBSID.
+pub(crate) fn write_solutions(
+    path: &Path,
+    solutions: &Solutions,
+    keys: &[(&str, i64)],
+) -> Result<(), Box<dyn Error>> {
+    if let Some(parent_dir) = path.parent() {
+        fs::create_dir_all(parent_dir)?;
+    }
+
+    let mut fptr = FitsFile::create(path).overwrite().open()?;
+
+    let primary_hdu = fptr.primary_hdu()?;
+    primary_hdu.write_key(&mut fptr, "OBSID", solutions.id as i64)?;
+    for (name, value) in keys {
+        primary_hdu.write_key(&mut fptr, name, *value)?;
+    }
+
+    // Real and imaginary parts are interleaved along the last axis
+    let description = ImageDescription {
+        data_type: ImageType::Double,
+        dimensions: &[1, solutions.num_tiles, solutions.num_chans, 8],
+    };
+    let sol_hdu = fptr.create_image("SOLUTIONS", &description)?;
+    let data: Vec<f64> = solutions
+        .complex_gains
+        .iter()
+        .flat_map(|c| [c.re, c.im])
+        .collect();
+    sol_hdu.write_image(&mut fptr, &data)?;
+
+    if let Some(names) = &solutions.tile_names {
+        let width = names.iter().map(String::len).max().unwrap_or(0).max(1);
+        let columns = [
+            ColumnDescription::new("Antenna")
+                .with_type(ColumnDataType::Int)
+                .create()?,
+            ColumnDescription::new("Flag")
+                .with_type(ColumnDataType::Int)
+                .create()?,
+            ColumnDescription::new("TileName")
+                .with_type(ColumnDataType::String)
+                .that_repeats(width)
+                .create()?,
+        ];
+        let tile_hdu = fptr.create_table("TILES", &columns)?;
+        let antennas: Vec<i32> = (0..solutions.num_tiles as i32).collect();
+        let flags = all_nan_flags(solutions, Axis(0));
+        tile_hdu.write_col(&mut fptr, "Antenna", &antennas)?;
+        tile_hdu.write_col(&mut fptr, "Flag", &flags)?;
+        tile_hdu.write_col(&mut fptr, "TileName", names)?;
+    }
+
+    if let Some(freqs) = &solutions.chan_freqs {
+        let columns = [
+            ColumnDescription::new("Index")
+                .with_type(ColumnDataType::Int)
+                .create()?,
+            ColumnDescription::new("Flag")
+                .with_type(ColumnDataType::Int)
+                .create()?,
+            ColumnDescription::new("Freq")
+                .with_type(ColumnDataType::Double)
+                .create()?,
+        ];
+        let chan_hdu = fptr.create_table("CHANBLOCKS", &columns)?;
+        let indices: Vec<i32> = (0..solutions.num_chans as i32).collect();
+        let flags = all_nan_flags(solutions, Axis(1));
+        chan_hdu.write_col(&mut fptr, "Index", &indices)?;
+        chan_hdu.write_col(&mut fptr, "Flag", &flags)?;
+        chan_hdu.write_col(&mut fptr, "Freq", &freqs.to_vec())?;
+    }
+
+    Ok(())
+}
+
+/// 1 for each tile or channel along `axis` without any gains, else 0
+fn all_nan_flags(solutions: &Solutions, axis: Axis) -> Vec<i32> {
+    solutions
+        .complex_gains
+        .axis_iter(axis)
+        .map(|gains| i32::from(gains.iter().all(|g| g.is_nan())))
+        .collect()
+}
+
+/// Copy the solutions file at `input` to `path`, replacing its SOLUTIONS HDU
+/// with `timeblocks`. Channels with gains in any timeblock are unflagged in
+/// CHANBLOCKS, if it has a Flag column. All other HDUs are kept.
+pub(crate) fn replace_solutions(
+    input: &Path,
+    path: &Path,
+    timeblocks: &[Solutions],
+) -> Result<(), Box<dyn Error>> {
+    if let Some(parent_dir) = path.parent() {
+        fs::create_dir_all(parent_dir)?;
+    }
+    fs::copy(input, path)?;
+
+    let mut fptr = FitsFile::edit(path)?;
+    let sol_hdu = fptr.hdu("SOLUTIONS")?;
+    let data: Vec<f64> = timeblocks
+        .iter()
+        .flat_map(|solutions| solutions.complex_gains.iter())
+        .flat_map(|c| [c.re, c.im])
+        .collect();
+    sol_hdu.write_section(&mut fptr, 0, data.len(), &data)?;
+
+    if let Ok(chan_hdu) = fptr.hdu("CHANBLOCKS")
+        && let Ok(mut flags) = chan_hdu.read_col::<i32>(&mut fptr, "Flag")
+    {
+        for solutions in timeblocks {
+            for (flag, gains) in flags
+                .iter_mut()
+                .zip(solutions.complex_gains.axis_iter(Axis(1)))
+            {
+                if gains.iter().any(|g| !g.is_nan()) {
+                    *flag = 0;
+                }
+            }
+        }
+        chan_hdu.write_col(&mut fptr, "Flag", &flags)?;
+    }
+
+    Ok(())
+}
+
+/// Write rows of values, each starting with its label
+pub(crate) fn write_labelled_rows(
+    path: &Path,
+    header: &[&str],
+    labels: &[String],
+    rows: &[Vec<f64>],
+) -> std::io::Result<()> {
+    if let Some(parent_dir) = path.parent() {
+        fs::create_dir_all(parent_dir)?;
+    }
+
+    let file = fs::File::create(path)?;
+    let mut writer = BufWriter::new(file);
+
+    for line in header {
+        writeln!(writer, "# {}", line)?;
+    }
+
+    for (label, row) in labels.iter().zip(rows) {
+        let line = row
+            .iter()
+            .map(|&val| format!("{:.10}", val))
+            .collect::<Vec<String>>()
+            .join(" ");
+        writeln!(writer, "{} {}", label, line)?;
+    }
+
+    Ok(())
+}
+
+/// Write a metric history in the format read by `HistoryFile`
+pub(crate) fn write_history(path: &Path, history: &History) -> std::io::Result<()> {
+    if let Some(parent_dir) = path.parent() {
+        fs::create_dir_all(parent_dir)?;
+    }
+
+    let file = fs::File::create(path)?;
+    let mut writer = BufWriter::new(file);
+
+    writeln!(writer, "# metric obsid tile value")?;
+    for ((metric, tile), values) in &history.values {
+        let tile = tile.as_deref().unwrap_or("-");
+        for (obsid, value) in values {
+            writeln!(writer, "{} {} {} {}", metric, obsid, tile, value)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write the per-observation table of a summary, with the median, 90th
+/// percentile, worst tile and worst value of each metric
+pub(crate) fn write_summary(
+    path: &Path,
+    metric_names: &[&str],
+    summary: &Summary,
+    longitude: f64,
+) -> std::io::Result<()> {
+    if let Some(parent_dir) = path.parent() {
+        fs::create_dir_all(parent_dir)?;
+    }
+
+    let file = fs::File::create(path)?;
+    let mut writer = BufWriter::new(file);
+
+    let metric_columns = metric_names
+        .iter()
+        .map(|name| format!("{0}_median {0}_p90 {0}_worst_tile {0}_worst", name))
+        .collect::<Vec<String>>()
+        .join(" ");
+    writeln!(
+        writer,
+        "# {} num_flagged_tiles flag_occupancy {}",
+        OBSID_COLUMNS, metric_columns
+    )?;
+
+    for (i, obsid) in summary.obsids.iter().enumerate() {
+        let line = summary.per_obs[i]
+            .iter()
+            .map(|m| {
+                format!(
+                    "{:.10} {:.10} {} {:.10}",
+                    m.median,
+                    m.p90,
+                    optional_index(m.worst_tile),
+                    m.worst
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(" ");
+        writeln!(
+            writer,
+            "{} {} {:.10} {}",
+            obsid_columns(*obsid, longitude),
+            summary.num_flagged_tiles[i],
+            summary.obs_flag_occupancy[i],
+            line
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Write the night-wide part of a summary: each metric over all tiles of all
+/// observations, followed by totals over the observations
+pub(crate) fn write_night_summary(
+    path: &Path,
+    metric_names: &[&str],
+    summary: &Summary,
+) -> std::io::Result<()> {
+    if let Some(parent_dir) = path.parent() {
+        fs::create_dir_all(parent_dir)?;
     }
 
+    let file = fs::File::create(path)?;
+    let mut writer = BufWriter::new(file);
+
+    writeln!(writer, "# metric median p90 worst_obsid worst_tile worst")?;
+    for (name, m) in metric_names.iter().zip(&summary.night) {
+        let MetricSummary {
+            median,
+            p90,
+            worst_obsid,
+            worst_tile,
+            worst,
+        } = m;
+        writeln!(
+            writer,
+            "{} {:.10} {:.10} {} {} {:.10}",
+            name,
+            median,
+            p90,
+            optional_index(*worst_obsid),
+            optional_index(*worst_tile),
+            worst
+        )?;
+    }
+
+    let known =
+        |values: &[f64]| -> Vec<f64> { values.iter().copied().filter(|v| !v.is_nan()).collect() };
+    let num_flagged_tiles = known(&summary.num_flagged_tiles);
+    let obs_flags = known(&summary.obs_flag_occupancy);
+
+    writeln!(writer, "# total value")?;
+    writeln!(writer, "num_observations {}", summary.obsids.len())?;
+    writeln!(
+        writer,
+        "num_flagged_tiles {}",
+        num_flagged_tiles.iter().sum::<f64>()
+    )?;
+    writeln!(
+        writer,
+        "mean_flag_occupancy {:.10}",
+        obs_flags.iter().sum::<f64>() / obs_flags.len() as f64
+    )?;
+
     Ok(())
 }
+
+/// Index, or "-" if there isn't one
+fn optional_index(index: Option<usize>) -> String {
+    index.map_or("-".to_string(), |i| i.to_string())
+}
+
+/// Write planes indexed like `Image::data` as a FITS image with the WCS,
+/// frequencies and Stokes parameters of `image`
+pub(crate) fn write_image_planes(
+    path: &Path,
+    image: &Image,
+    data: &Array4<f64>,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(parent_dir) = path.parent() {
+        fs::create_dir_all(parent_dir)?;
+    }
+
+    // fitsio takes the dimensions in the reverse of the FITS axis order,
+    // which matches `Image::data`
+    let description = ImageDescription {
+        data_type: ImageType::Double,
+        dimensions: data.shape(),
+    };
+    let mut fptr = FitsFile::create(path)
+        .with_custom_primary(&description)
+        .overwrite()
+        .open()?;
+    let hdu = fptr.primary_hdu()?;
+    let data = data.as_standard_layout();
+    hdu.write_image(&mut fptr, data.as_slice().expect("standard layout"))?;
+
+    hdu.write_key(&mut fptr, "OBSID", image.id as i64)?;
+    if let Some(wcs) = &image.wcs {
+        wcs.write(&mut fptr, &hdu)?;
+    }
+    if let Some(beam) = &image.beam {
+        for (key, value) in ["BMAJ", "BMIN", "BPA"].iter().zip(beam) {
+            hdu.write_key(&mut fptr, key, *value)?;
+        }
+    }
+    for (axis, ctype, values) in [(3, "FREQ", &image.freqs), (4, "STOKES", &image.stokes)] {
+        if values.iter().any(|v| v.is_nan()) {
+            continue;
+        }
+        let step = if values.len() > 1 {
+            values[1] - values[0]
+        } else {
+            1.0
+        };
+        hdu.write_key(&mut fptr, &format!("CTYPE{}", axis), ctype)?;
+        hdu.write_key(&mut fptr, &format!("CRPIX{}", axis), 1.0)?;
+        hdu.write_key(&mut fptr, &format!("CRVAL{}", axis), values[0])?;
+        hdu.write_key(&mut fptr, &format!("CDELT{}", axis), step)?;
+    }
+
+    Ok(())
+}
+
+/// Write a source catalogue as CSV, one row per source
+pub(crate) fn write_catalogue(path: &Path, sources: &[Source]) -> std::io::Result<()> {
+    if let Some(parent_dir) = path.parent() {
+        fs::create_dir_all(parent_dir)?;
+    }
+
+    let file = fs::File::create(path)?;
+    let mut writer = BufWriter::new(file);
+
+    writeln!(
+        writer,
+        "stokes,freq,island,x,y,ra,dec,peak_flux,peak_snr,int_flux,major_pix,minor_pix,pa,background,rms,num_pixels"
+    )?;
+    for source in sources {
+        let values = [
+            source.x,
+            source.y,
+            source.ra,
+            source.dec,
+            source.peak_flux,
+            source.peak_snr,
+            source.int_flux,
+            source.major,
+            source.minor,
+            source.pa,
+            source.background,
+            source.rms,
+        ]
+        .iter()
+        .map(|v| format!("{:.10}", v))
+        .collect::<Vec<String>>()
+        .join(",");
+        writeln!(
+            writer,
+            "{},{},{},{},{}",
+            source.stokes, source.freq, source.island, values, source.num_pixels
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::read::solutions::CalSolFile;
+    use ndarray::Zip;
+    use ndarray::prelude::*;
+    use num_complex::Complex64;
+
+    #[test]
+    fn solutions_round_trip() {
+        let (num_tiles, num_chans) = (3, 8);
+        let mut complex_gains = Array3::from_shape_fn((num_tiles, num_chans, 4), |(t, c, p)| {
+            Complex64::new(t as f64 + 0.1 * c as f64, p as f64 - 0.5)
+        });
+        let nan = Complex64::new(f64::NAN, f64::NAN);
+        complex_gains.slice_mut(s![1, .., ..]).fill(nan);
+        complex_gains.slice_mut(s![.., 2, ..]).fill(nan);
+        let solutions = Solutions {
+            complex_gains,
+            id: 1_090_008_640,
+            num_tiles,
+            num_chans,
+            chan_freqs: Some(Array1::range(0.0, num_chans as f64, 1.0) * 40e3 + 167e6),
+            tile_names: Some(vec!["Tile011".into(), "HexE1".into(), "LBA1".into()]),
+        };
+
+        let path = std::env::temp_dir().join(format!("calmet_write_{}.fits", std::process::id()));
+        write_solutions(&path, &solutions, &[("NOBS", 3)]).unwrap();
+        let read = CalSolFile {
+            file_path: path.clone(),
+        }
+        .read_fits()
+        .unwrap();
+
+        let mut fptr = FitsFile::open(&path).unwrap();
+        let nobs: i64 = fptr.hdu(0).unwrap().read_key(&mut fptr, "NOBS").unwrap();
+        let tile_hdu = fptr.hdu("TILES").unwrap();
+        let tile_flags: Vec<i32> = tile_hdu.read_col(&mut fptr, "Flag").unwrap();
+        let chan_hdu = fptr.hdu("CHANBLOCKS").unwrap();
+        let chan_flags: Vec<i32> = chan_hdu.read_col(&mut fptr, "Flag").unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.id, solutions.id);
+        assert_eq!((read.num_tiles, read.num_chans), (num_tiles, num_chans));
+        assert_eq!(read.chan_freqs, solutions.chan_freqs);
+        assert_eq!(read.tile_names, solutions.tile_names);
+        Zip::from(&read.complex_gains)
+            .and(&solutions.complex_gains)
+            .for_each(|r, s| assert!(r == s || (r.is_nan() && s.is_nan())));
+
+        assert_eq!(nobs, 3);
+        assert_eq!(tile_flags, vec![0, 1, 0]);
+        assert_eq!(chan_flags, vec![0, 0, 1, 0, 0, 0, 0, 0]);
+    }
+}
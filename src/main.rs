@@ -1,6 +1,7 @@
 mod cli;
 mod io;
 mod metrics;
+mod time;
 
 use clap::Parser;
 use std::process;
@@ -8,7 +9,7 @@ use std::process;
 fn main() {
     let command = cli::Cli::parse();
 
-    if let Err(e) = command.sub_command.run() {
+    if let Err(e) = command.sub_command.run(command.longitude) {
         eprintln!("Error: {}", e);
         process::exit(1);
     };
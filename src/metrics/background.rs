@@ -0,0 +1,288 @@
+use crate::io::read::image::{Image, ImageFile};
+use crate::metrics::image::{median, sigma_clipped_stats};
+use ndarray::Zip;
+use ndarray::prelude::*;
+use rayon::prelude::*;
+use regex::Regex;
+use std::error::Error;
+use std::path::Path;
+
+/// Fewest valid pixels a box needs for its background and RMS
+const MIN_BOX_PIXELS: usize = 10;
+
+/// Distance from the image centre within which pixels are in the centre, as
+/// a fraction of half the smaller image dimension
+pub(crate) const CENTRE_FRACTION: f64 = 0.2;
+
+/// Distance from the image centre beyond which pixels are at the edge, as a
+/// fraction of half the smaller image dimension
+pub(crate) const EDGE_FRACTION: f64 = 0.8;
+
+/// Background and RMS maps of every plane of an image, indexed like
+/// `Image::data`
+#[derive(Debug)]
+pub(crate) struct NoiseMaps {
+    pub(crate) background: Array4<f64>,
+    pub(crate) rms: Array4<f64>,
+}
+
+/// Summary of the noise maps of a single Stokes and frequency plane
+#[derive(Debug)]
+pub(crate) struct MapSummary {
+    // FITS Stokes code, NaN if the image has no Stokes axis
+    pub(crate) stokes: f64,
+
+    // Frequency [Hz], NaN if the image has no frequency axis
+    pub(crate) freq: f64,
+
+    pub(crate) median_background: f64,
+    pub(crate) median_rms: f64,
+    pub(crate) min_rms: f64,
+    pub(crate) max_rms: f64,
+
+    // Median RMS of the pixels near the centre and near the edge of the image
+    pub(crate) centre_rms: f64,
+    pub(crate) edge_rms: f64,
+}
+
+impl MapSummary {
+    /// Edge RMS over centre RMS, which is well above 1 for primary beam
+    /// corrected images or those with artefacts near the edge
+    pub(crate) fn edge_to_centre(&self) -> f64 {
+        self.edge_rms / self.centre_rms
+    }
+}
+
+/// Image with its background and RMS maps and their summary per plane
+pub(crate) fn run_noise_map_calc(
+    path: &Path,
+    obsid_pattern: &Regex,
+    grid: usize,
+    box_size: usize,
+) -> Result<(Image, NoiseMaps, Vec<MapSummary>), Box<dyn Error>> {
+    let image = ImageFile {
+        file_path: path.to_path_buf(),
+        obsid_pattern: obsid_pattern.clone(),
+    }
+    .read_fits()?;
+
+    let maps = calc_noise_maps(&image, grid, box_size);
+    let summary = summarise_maps(&image, &maps);
+    Ok((image, maps, summary))
+}
+
+/// Background and RMS maps in the style of BANE. The sigma clipped median and
+/// standard deviation of a `box_size` box of pixels are found every `grid`
+/// pixels, then bilinearly interpolated to every pixel. Pixels that are NaN in
+/// the image are NaN in the maps.
+pub(crate) fn calc_noise_maps(image: &Image, grid: usize, box_size: usize) -> NoiseMaps {
+    let mut background = Array4::from_elem(image.data.dim(), f64::NAN);
+    let mut rms = Array4::from_elem(image.data.dim(), f64::NAN);
+
+    let (num_stokes, num_freqs, _, _) = image.data.dim();
+    for stokes in 0..num_stokes {
+        for freq in 0..num_freqs {
+            let plane = image.data.slice(s![stokes, freq, .., ..]);
+            let (plane_background, plane_rms) = plane_maps(plane, grid.max(1), box_size);
+            background
+                .slice_mut(s![stokes, freq, .., ..])
+                .assign(&plane_background);
+            rms.slice_mut(s![stokes, freq, .., ..]).assign(&plane_rms);
+        }
+    }
+
+    NoiseMaps { background, rms }
+}
+
+fn plane_maps(plane: ArrayView2<f64>, grid: usize, box_size: usize) -> (Array2<f64>, Array2<f64>) {
+    let (num_y, num_x) = plane.dim();
+    let grid_y = grid_points(num_y, grid);
+    let grid_x = grid_points(num_x, grid);
+    let half = box_size / 2;
+
+    // Statistics of the box around each grid point
+    let points: Vec<(usize, usize)> = grid_y
+        .iter()
+        .flat_map(|&y| grid_x.iter().map(move |&x| (y, x)))
+        .collect();
+    let stats: Vec<(f64, f64)> = points
+        .par_iter()
+        .map(|&(y, x)| {
+            let window = plane.slice(s![
+                y.saturating_sub(half)..(y + half + 1).min(num_y),
+                x.saturating_sub(half)..(x + half + 1).min(num_x)
+            ]);
+            let valid: Vec<f64> = window.iter().copied().filter(|v| v.is_finite()).collect();
+            if valid.len() < MIN_BOX_PIXELS {
+                (f64::NAN, f64::NAN)
+            } else {
+                sigma_clipped_stats(&valid)
+            }
+        })
+        .collect();
+    let coarse_shape = (grid_y.len(), grid_x.len());
+    let coarse_background =
+        Array2::from_shape_fn(coarse_shape, |(i, j)| stats[i * grid_x.len() + j].0);
+    let coarse_rms = Array2::from_shape_fn(coarse_shape, |(i, j)| stats[i * grid_x.len() + j].1);
+
+    let mut background = Array2::from_elem((num_y, num_x), f64::NAN);
+    let mut rms = Array2::from_elem((num_y, num_x), f64::NAN);
+    Zip::indexed(&mut background)
+        .and(&mut rms)
+        .and(&plane)
+        .par_for_each(|(y, x), b, r, &v| {
+            if v.is_finite() {
+                let (i, wy) = cell(&grid_y, y);
+                let (j, wx) = cell(&grid_x, x);
+                *b = bilinear(&coarse_background, i, j, wy, wx);
+                *r = bilinear(&coarse_rms, i, j, wy, wx);
+            }
+        });
+
+    (background, rms)
+}
+
+/// Grid points every `grid` pixels along an axis of `len` pixels, always
+/// including the last pixel so the edges are covered. Empty if `len` is 0.
+fn grid_points(len: usize, grid: usize) -> Vec<usize> {
+    let mut points: Vec<usize> = (0..len).step_by(grid).collect();
+    if let Some(last) = len.checked_sub(1)
+        && points.last() != Some(&last)
+    {
+        points.push(last);
+    }
+    points
+}
+
+/// Index of the grid point at or before `pixel` and the fraction of the way
+/// to the next one
+fn cell(points: &[usize], pixel: usize) -> (usize, f64) {
+    if points.len() < 2 {
+        return (0, 0.0);
+    }
+    let i = points
+        .partition_point(|&p| p <= pixel)
+        .saturating_sub(1)
+        .min(points.len() - 2);
+    let fraction = (pixel - points[i]) as f64 / (points[i + 1] - points[i]) as f64;
+    (i, fraction)
+}
+
+/// Bilinear interpolation within the cell starting at (i, j), ignoring NaN
+/// corners. NaN if every corner is NaN.
+fn bilinear(coarse: &Array2<f64>, i: usize, j: usize, wy: f64, wx: f64) -> f64 {
+    let (num_i, num_j) = coarse.dim();
+    let (sum, weights) = [
+        (i, j, (1.0 - wy) * (1.0 - wx)),
+        (i, j + 1, (1.0 - wy) * wx),
+        (i + 1, j, wy * (1.0 - wx)),
+        (i + 1, j + 1, wy * wx),
+    ]
+    .iter()
+    .filter(|&&(ci, cj, w)| ci < num_i && cj < num_j && w > 0.0 && coarse[[ci, cj]].is_finite())
+    .fold((0.0, 0.0), |(s, t), &(ci, cj, w)| {
+        (s + w * coarse[[ci, cj]], t + w)
+    });
+    sum / weights
+}
+
+/// Median background and RMS of each plane, with the median RMS near the
+/// centre and edge of the image
+pub(crate) fn summarise_maps(image: &Image, maps: &NoiseMaps) -> Vec<MapSummary> {
+    let (num_stokes, num_freqs, num_y, num_x) = image.data.dim();
+
+    // Distance of each pixel from the image centre as a fraction of half the
+    // smaller dimension
+    let (centre_y, centre_x) = ((num_y as f64 - 1.0) / 2.0, (num_x as f64 - 1.0) / 2.0);
+    let half_size = num_y.min(num_x) as f64 / 2.0;
+    let distance = Array2::from_shape_fn((num_y, num_x), |(y, x)| {
+        (y as f64 - centre_y).hypot(x as f64 - centre_x) / half_size
+    });
+
+    let mut summaries = vec![];
+    for stokes in 0..num_stokes {
+        for freq in 0..num_freqs {
+            let rms = maps.rms.slice(s![stokes, freq, .., ..]);
+            let finite = |keep: &dyn Fn(f64) -> bool| -> Vec<f64> {
+                rms.iter()
+                    .zip(&distance)
+                    .filter(|&(v, &d)| v.is_finite() && keep(d))
+                    .map(|(&v, _)| v)
+                    .collect()
+            };
+            let mut all_rms = finite(&|_| true);
+            let mut background: Vec<f64> = maps
+                .background
+                .slice(s![stokes, freq, .., ..])
+                .iter()
+                .copied()
+                .filter(|v| v.is_finite())
+                .collect();
+
+            summaries.push(MapSummary {
+                stokes: image.stokes[stokes],
+                freq: image.freqs[freq],
+                median_background: median(&mut background),
+                min_rms: all_rms.iter().copied().fold(f64::NAN, f64::min),
+                max_rms: all_rms.iter().copied().fold(f64::NAN, f64::max),
+                median_rms: median(&mut all_rms),
+                centre_rms: median(&mut finite(&|d| d <= CENTRE_FRACTION)),
+                edge_rms: median(&mut finite(&|d| d >= EDGE_FRACTION)),
+            });
+        }
+    }
+
+    summaries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_points_cover_the_axis() {
+        assert_eq!(grid_points(10, 3), vec![0, 3, 6, 9]);
+        assert_eq!(grid_points(11, 3), vec![0, 3, 6, 9, 10]);
+        assert_eq!(grid_points(5, 10), vec![0, 4]);
+        assert_eq!(grid_points(1, 3), vec![0]);
+        assert!(grid_points(0, 3).is_empty());
+    }
+
+    #[test]
+    fn cell_of_pixels() {
+        let points = [0, 3, 6, 9, 10];
+        assert_eq!(cell(&points, 0), (0, 0.0));
+        assert_eq!(cell(&points, 4), (1, 1.0 / 3.0));
+        assert_eq!(cell(&points, 9), (3, 0.0));
+        assert_eq!(cell(&points, 10), (3, 1.0));
+        assert_eq!(cell(&[0], 0), (0, 0.0));
+    }
+
+    #[test]
+    fn bilinear_interpolates_and_skips_nan() {
+        let coarse = array![[0.0, 2.0], [4.0, 6.0]];
+        assert_eq!(bilinear(&coarse, 0, 0, 0.0, 0.0), 0.0);
+        assert_eq!(bilinear(&coarse, 0, 0, 0.5, 0.5), 3.0);
+        assert_eq!(bilinear(&coarse, 0, 0, 0.25, 1.0), 3.0);
+
+        // Only the finite corners are weighted
+        let coarse = array![[f64::NAN, 2.0], [4.0, 6.0]];
+        assert_eq!(bilinear(&coarse, 0, 0, 0.5, 0.5), 4.0);
+        assert!(bilinear(&array![[f64::NAN]], 0, 0, 0.0, 0.0).is_nan());
+    }
+
+    #[test]
+    fn flat_plane_maps() {
+        let mut plane = Array2::from_elem((20, 20), 5.0);
+        plane[[7, 3]] = f64::NAN;
+        let (background, rms) = plane_maps(plane.view(), 5, 7);
+        assert!(background[[7, 3]].is_nan() && rms[[7, 3]].is_nan());
+        assert_eq!(background.iter().filter(|b| b.is_nan()).count(), 1);
+        assert!(
+            background
+                .iter()
+                .all(|&b| b.is_nan() || (b - 5.0).abs() < 1e-12)
+        );
+        assert!(rms.iter().all(|&r| r.is_nan() || r == 0.0));
+    }
+}
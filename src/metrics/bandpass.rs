@@ -0,0 +1,181 @@
+use crate::io::read::solutions::{CalSolFile, Solutions};
+use crate::metrics::gain_phase::fit_residuals;
+use ndarray::{Zip, prelude::*};
+use ndarray_stats::QuantileExt;
+use ndarray_stats::interpolate::Linear;
+use noisy_float::types::n64;
+use num_complex::Complex64;
+use std::error::Error;
+use std::path::PathBuf;
+
+/// Normalised amplitude and phase bandpasses, indexed [pol, tile, chan] with
+/// XX then YY along the first axis
+#[derive(Debug)]
+pub(crate) struct Bandpasses {
+    // Amplitude divided by the tile's median amplitude
+    pub(crate) amps: Array3<f64>,
+
+    // Unwrapped phase minus its straight-line fit [rad]
+    pub(crate) phases: Array3<f64>,
+}
+
+/// Reduce each tile's XX and YY gains to the shape of their bandpass, so that
+/// overall gain and delay differences between observations are removed
+pub(crate) fn normalised_bandpasses(solutions: &Solutions) -> Result<Bandpasses, Box<dyn Error>> {
+    let channels = Array1::<f64>::range(0.0, solutions.num_chans as f64, 1.0);
+    let shape = (2, solutions.num_tiles, solutions.num_chans);
+    let mut amps = Array3::<f64>::from_elem(shape, f64::NAN);
+    let mut phases = Array3::<f64>::from_elem(shape, f64::NAN);
+
+    for (pol, pol_index) in [0, 3].into_iter().enumerate() {
+        let gains = solutions.complex_gains.slice(s![.., .., pol_index]);
+
+        let mut pol_amps = gains.map(|c| c.norm());
+        let tile_medians =
+            pol_amps
+                .clone()
+                .quantile_axis_skipnan_mut(Axis(1), n64(0.5), &Linear)?;
+        pol_amps /= &tile_medians.insert_axis(Axis(1));
+        amps.slice_mut(s![pol, .., ..]).assign(&pol_amps);
+
+        let pol_phases = gains.map(|c| c.arg());
+        Zip::from(phases.slice_mut(s![pol, .., ..]).rows_mut())
+            .and(pol_phases.rows())
+            .for_each(|mut out, angs| out.assign(&fit_residuals(&angs, &channels)));
+    }
+
+    Ok(Bandpasses { amps, phases })
+}
+
+/// Wrap a phase to [-pi, pi]
+pub(crate) fn wrap_phase(phase: f64) -> f64 {
+    phase.sin().atan2(phase.cos())
+}
+
+/// RMS of the non-NaN values, NaN if there are none
+pub(crate) fn nan_rms<'a>(values: impl Iterator<Item = &'a f64>) -> f64 {
+    let (sum_sq, num) = values
+        .filter(|v| !v.is_nan())
+        .fold((0.0, 0), |(s, n), v| (s + v * v, n + 1));
+    if num == 0 {
+        f64::NAN
+    } else {
+        (sum_sq / num as f64).sqrt()
+    }
+}
+
+/// Per-tile median complex bandpass over a set of observations, along with
+/// the number of observations used. Solutions are aligned to the tiles and
+/// channels of the first file.
+///
+/// Each observation's gains are first rotated onto the first observation's,
+/// removing the phase offset per tile and polarisation, then the median of
+/// the real and imaginary parts is taken separately.
+pub(crate) fn median_bandpass(paths: &[PathBuf]) -> Result<(Solutions, usize), Box<dyn Error>> {
+    let mut all_solutions = paths.iter().filter_map(|path| {
+        let file = CalSolFile {
+            file_path: path.to_path_buf(),
+        };
+        file.read_fits()
+            .inspect_err(|_| eprintln!("Warning: unable to read {}", path.display()))
+            .ok()
+    });
+
+    let first = all_solutions.next().ok_or("No solutions could be read")?;
+    let mut aligned = vec![first.complex_gains.clone()];
+    for solutions in all_solutions {
+        match align_to(&solutions, &first) {
+            Some(gains) => aligned.push(gains),
+            None => eprintln!(
+                "Warning: channels of obsid {} don't match obsid {}, skipping",
+                solutions.id, first.id
+            ),
+        }
+    }
+
+    // Rotate each observation onto the first, separately for each
+    // tile and polarisation
+    let reference = aligned[0].clone();
+    for gains in aligned.iter_mut().skip(1) {
+        Zip::from(gains.lanes_mut(Axis(1)))
+            .and(reference.lanes(Axis(1)))
+            .for_each(|mut obs, reference| {
+                let cross: Complex64 = obs
+                    .iter()
+                    .zip(reference)
+                    .map(|(o, r)| o * r.conj())
+                    .filter(|c| !c.is_nan())
+                    .sum();
+                let rotation = Complex64::from_polar(1.0, -cross.arg());
+                obs.mapv_inplace(|o| o * rotation);
+            });
+    }
+
+    let views: Vec<_> = aligned.iter().map(|g| g.view()).collect();
+    let stacked = ndarray::stack(Axis(0), &views)?;
+    let median_re = stacked
+        .mapv(|c| c.re)
+        .quantile_axis_skipnan_mut(Axis(0), n64(0.5), &Linear)?;
+    let median_im = stacked
+        .mapv(|c| c.im)
+        .quantile_axis_skipnan_mut(Axis(0), n64(0.5), &Linear)?;
+    let complex_gains = Zip::from(&median_re)
+        .and(&median_im)
+        .map_collect(|&re, &im| Complex64::new(re, im));
+
+    let result = Solutions {
+        complex_gains,
+        id: first.id,
+        num_tiles: first.num_tiles,
+        num_chans: first.num_chans,
+        chan_freqs: first.chan_freqs,
+        tile_names: first.tile_names,
+    };
+
+    Ok((result, aligned.len()))
+}
+
+/// Gains of `solutions` on the tiles and channels of `target`. Tiles are
+/// matched as in `Solutions::tile_map`, and channels by frequency when both
+/// have them, otherwise by index. Missing tiles and channels are NaN.
+pub(crate) fn align_to(solutions: &Solutions, target: &Solutions) -> Option<Array3<Complex64>> {
+    let nan = Complex64::new(f64::NAN, f64::NAN);
+    let mut aligned = Array3::from_elem(target.complex_gains.dim(), nan);
+    let tile_map = solutions.tile_map(target);
+
+    let chan_map: Vec<Option<usize>> = match (&solutions.chan_freqs, &target.chan_freqs) {
+        (Some(freqs), Some(target_freqs)) => {
+            let tolerance = target_freqs
+                .windows(2)
+                .into_iter()
+                .map(|w| (w[1] - w[0]).abs())
+                .fold(f64::INFINITY, f64::min)
+                / 2.0;
+            target_freqs
+                .iter()
+                .map(|t| freqs.iter().position(|f| (f - t).abs() < tolerance))
+                .collect()
+        }
+        _ if solutions.num_chans == target.num_chans => (0..target.num_chans).map(Some).collect(),
+        _ => return None,
+    };
+
+    if chan_map.iter().all(Option::is_none) {
+        return None;
+    }
+
+    for (target_tile, tile) in tile_map.iter().enumerate() {
+        let Some(tile) = tile else {
+            continue;
+        };
+        for (target_chan, chan) in chan_map.iter().enumerate() {
+            if let Some(chan) = chan {
+                aligned
+                    .slice_mut(s![target_tile, target_chan, ..])
+                    .assign(&solutions.complex_gains.slice(s![*tile, *chan, ..]));
+            }
+        }
+    }
+
+    Some(aligned)
+}
@@ -0,0 +1,234 @@
+use crate::io::read::solutions::CalSolFile;
+use crate::metrics::bandpass::normalised_bandpasses;
+use crate::metrics::interp::InterpolateNans;
+use ndarray::prelude::*;
+use std::error::Error;
+use std::path::Path;
+
+/// Maximum number of k-means assignment/update rounds
+const MAX_ITERATIONS: usize = 100;
+
+/// K-means clusters of the tiles in one observation
+#[derive(Debug)]
+pub(crate) struct Clusters {
+    pub(crate) obsid: usize,
+
+    // Unflagged tiles that were clustered
+    pub(crate) tiles: Vec<usize>,
+
+    // Cluster of each tile in `tiles`
+    pub(crate) labels: Vec<usize>,
+
+    // Euclidean distance of each tile in `tiles` from its cluster centroid
+    pub(crate) distances: Vec<f64>,
+
+    // Mean feature vector of each cluster, indexed [cluster, feature]. The
+    // features are the XX and YY normalised amplitudes followed by the XX and
+    // YY phase residuals.
+    pub(crate) centroids: Array2<f64>,
+}
+
+impl Clusters {
+    /// Number of tiles in each cluster
+    pub(crate) fn sizes(&self) -> Vec<usize> {
+        let mut sizes = vec![0; self.centroids.nrows()];
+        for &label in &self.labels {
+            sizes[label] += 1;
+        }
+        sizes
+    }
+}
+
+/// Cluster the unflagged tiles of an observation by the shape of their
+/// normalised amplitude and phase bandpasses
+pub(crate) fn run_cluster_calc(
+    file_path: &Path,
+    num_clusters: usize,
+) -> Result<Clusters, Box<dyn Error>> {
+    let file = CalSolFile {
+        file_path: file_path.to_path_buf(),
+    };
+    let solutions = file.read_fits()?;
+    let bandpasses = normalised_bandpasses(&solutions)?;
+
+    let mut tiles = vec![];
+    let mut rows: Vec<Array1<f64>> = vec![];
+    'tiles: for tile in 0..solutions.num_tiles {
+        // Interpolate each polarisation's amplitudes and phases on their own
+        // so gaps aren't filled from a neighbouring segment
+        let mut segments = vec![];
+        for bandpass in [&bandpasses.amps, &bandpasses.phases] {
+            for pol in 0..bandpass.len_of(Axis(0)) {
+                let mut segment = bandpass.slice(s![pol, tile, ..]).to_owned();
+
+                // Flagged tiles are all NaN, and a tile missing any segment
+                // can't be compared with the rest
+                if segment.iter().all(|v| v.is_nan()) {
+                    continue 'tiles;
+                }
+                segment.interp_nans_inplace();
+                segments.push(segment);
+            }
+        }
+
+        tiles.push(tile);
+        rows.push(Array1::from_iter(segments.iter().flatten().copied()));
+    }
+
+    if num_clusters == 0 {
+        return Err("Need at least one cluster".into());
+    }
+    if tiles.len() < num_clusters {
+        return Err(format!(
+            "Obsid {} has {} unflagged tiles, fewer than {} clusters",
+            solutions.id,
+            tiles.len(),
+            num_clusters
+        )
+        .into());
+    }
+
+    let views: Vec<_> = rows.iter().map(|r| r.view()).collect();
+    let data = ndarray::stack(Axis(0), &views)?;
+    let (labels, distances, centroids) = k_means(&data, num_clusters);
+
+    Ok(Clusters {
+        obsid: solutions.id,
+        tiles,
+        labels,
+        distances,
+        centroids,
+    })
+}
+
+/// K-means clustering of the rows of `data`, returning the label and distance
+/// to the centroid of each row along with the centroids.
+///
+/// Centroids are seeded deterministically by farthest-point selection, starting
+/// from the row closest to the overall mean, so that a small group of faulty
+/// tiles gets its own seed rather than being absorbed by the bulk of the array.
+fn k_means(data: &Array2<f64>, num_clusters: usize) -> (Vec<usize>, Vec<f64>, Array2<f64>) {
+    let num_features = data.ncols();
+    let mean = data.mean_axis(Axis(0)).expect("No rows to cluster");
+
+    let mut centroids = Array2::<f64>::zeros((num_clusters, num_features));
+    let first = closest(data, &mean.view());
+    centroids.row_mut(0).assign(&data.row(first));
+    for cluster in 1..num_clusters {
+        let farthest = data
+            .rows()
+            .into_iter()
+            .map(|row| nearest(&row, &centroids.slice(s![..cluster, ..])).1)
+            .enumerate()
+            .fold(
+                (0, f64::MIN),
+                |best, (i, d)| if d > best.1 { (i, d) } else { best },
+            )
+            .0;
+        centroids.row_mut(cluster).assign(&data.row(farthest));
+    }
+
+    let mut labels = vec![usize::MAX; data.nrows()];
+    for _ in 0..MAX_ITERATIONS {
+        let new_labels: Vec<usize> = data
+            .rows()
+            .into_iter()
+            .map(|row| nearest(&row, &centroids.view()).0)
+            .collect();
+        if new_labels == labels {
+            break;
+        }
+        labels = new_labels;
+
+        for (cluster, mut centroid) in centroids.rows_mut().into_iter().enumerate() {
+            let members: Vec<_> = labels
+                .iter()
+                .zip(data.rows())
+                .filter(|(label, _)| **label == cluster)
+                .map(|(_, row)| row)
+                .collect();
+            // Keep the previous centroid if a cluster empties
+            if !members.is_empty() {
+                let stacked = ndarray::stack(Axis(0), &members).expect("Rows differ in length");
+                centroid.assign(&stacked.mean_axis(Axis(0)).expect("No members"));
+            }
+        }
+    }
+
+    let distances = data
+        .rows()
+        .into_iter()
+        .zip(&labels)
+        .map(|(row, &label)| distance(&row, &centroids.row(label)))
+        .collect();
+
+    (labels, distances, centroids)
+}
+
+/// Index of the row of `data` closest to `point`
+fn closest(data: &Array2<f64>, point: &ArrayView1<f64>) -> usize {
+    nearest(point, &data.view()).0
+}
+
+/// Index of and distance to the row of `centroids` nearest to `row`
+fn nearest(row: &ArrayView1<f64>, centroids: &ArrayView2<f64>) -> (usize, f64) {
+    centroids
+        .rows()
+        .into_iter()
+        .map(|centroid| distance(row, &centroid))
+        .enumerate()
+        .fold(
+            (0, f64::INFINITY),
+            |best, (i, d)| if d < best.1 { (i, d) } else { best },
+        )
+}
+
+fn distance(a: &ArrayView1<f64>, b: &ArrayView1<f64>) -> f64 {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| (x - y).powi(2))
+        .sum::<f64>()
+        .sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separates_two_groups() {
+        // A bulk of tiles scattered around the origin and a small group far
+        // away, interleaved so the labels don't follow the row order
+        let data = Array2::from_shape_fn((12, 3), |(i, j)| {
+            let jitter = 0.01 * ((i * 3 + j) % 5) as f64;
+            if i % 4 == 1 { 10.0 + jitter } else { jitter }
+        });
+        let (labels, distances, centroids) = k_means(&data, 2);
+
+        let far = labels[1];
+        for (i, &label) in labels.iter().enumerate() {
+            assert_eq!(label == far, i % 4 == 1, "tile {i}");
+        }
+        assert!(distances.iter().all(|&d| d < 0.1));
+
+        let near = 1 - far;
+        let far_rows = data.select(Axis(0), &[1, 5, 9]);
+        let near_rows = data.select(Axis(0), &[0, 2, 3, 4, 6, 7, 8, 10, 11]);
+        for (centroid, rows) in [(far, far_rows), (near, near_rows)] {
+            let mean = rows.mean_axis(Axis(0)).unwrap();
+            for (c, m) in centroids.row(centroid).iter().zip(&mean) {
+                assert!((c - m).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn one_cluster_is_the_mean() {
+        let data = array![[0.0, 1.0], [2.0, 3.0], [4.0, 5.0]];
+        let (labels, distances, centroids) = k_means(&data, 1);
+        assert_eq!(labels, vec![0; 3]);
+        assert_eq!(centroids, array![[2.0, 3.0]]);
+        assert!((distances[0] - 8f64.sqrt()).abs() < 1e-12);
+        assert_eq!(distances[1], 0.0);
+    }
+}
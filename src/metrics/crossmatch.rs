@@ -0,0 +1,196 @@
+use crate::io::read::catalogue::ReferenceSource;
+use crate::io::read::wcs::angular_distance;
+use crate::metrics::image::{calc_mad, median};
+use crate::metrics::sources::{Source, SourceCounts};
+
+/// Comparison of the sources of a single Stokes and frequency plane with a
+/// reference catalogue
+#[derive(Debug)]
+pub(crate) struct CrossmatchSummary {
+    pub(crate) stokes: f64,
+    pub(crate) freq: f64,
+
+    pub(crate) num_sources: usize,
+    pub(crate) num_matches: usize,
+
+    // Median and MAD scatter of the measured over the reference flux density
+    pub(crate) median_flux_ratio: f64,
+    pub(crate) flux_ratio_scatter: f64,
+
+    // Median measured minus reference position [arcsec], with the RA offset
+    // scaled by cos(Dec) so it's an angle on the sky
+    pub(crate) median_ra_offset: f64,
+    pub(crate) median_dec_offset: f64,
+}
+
+/// Match the sources of each plane in `counts` to the nearest reference source
+/// within `radius` [deg], and compare their flux densities and positions.
+///
+/// Reference flux densities are extrapolated from `ref_freq` [Hz] to the
+/// frequency of each plane with their spectral index, or `default_alpha` if
+/// they don't have one. Planes without a frequency aren't extrapolated. The
+/// integrated flux density of sources is used if known, else the peak.
+pub(crate) fn crossmatch(
+    sources: &[Source],
+    counts: &[SourceCounts],
+    reference: &[ReferenceSource],
+    radius: f64,
+    ref_freq: f64,
+    default_alpha: f64,
+) -> Vec<CrossmatchSummary> {
+    // Sort by Dec to only search the band of reference sources near each
+    // source
+    let mut reference: Vec<&ReferenceSource> = reference.iter().collect();
+    reference.sort_by(|a, b| a.dec.total_cmp(&b.dec));
+
+    let nearest = |source: &Source| -> Option<&ReferenceSource> {
+        let start = reference.partition_point(|r| r.dec < source.dec - radius);
+        let end = reference.partition_point(|r| r.dec <= source.dec + radius);
+        reference[start..end]
+            .iter()
+            .map(|r| (r, angular_distance(source.ra, source.dec, r.ra, r.dec)))
+            .filter(|&(_, distance)| distance <= radius)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(r, _)| *r)
+    };
+
+    counts
+        .iter()
+        .map(|plane| {
+            // NaN Stokes and frequency still identify the plane
+            let plane_sources: Vec<&Source> = sources
+                .iter()
+                .filter(|s| {
+                    s.stokes.to_bits() == plane.stokes.to_bits()
+                        && s.freq.to_bits() == plane.freq.to_bits()
+                })
+                .collect();
+
+            let (mut ratios, mut ra_offsets, mut dec_offsets) = (vec![], vec![], vec![]);
+            for source in &plane_sources {
+                if !(source.ra.is_finite() && source.dec.is_finite()) {
+                    continue;
+                }
+                let Some(matched) = nearest(source) else {
+                    continue;
+                };
+
+                let alpha = if matched.alpha.is_finite() {
+                    matched.alpha
+                } else {
+                    default_alpha
+                };
+                let scale = if plane.freq.is_finite() {
+                    (plane.freq / ref_freq).powf(alpha)
+                } else {
+                    1.0
+                };
+                let measured = if source.int_flux.is_finite() {
+                    source.int_flux
+                } else {
+                    source.peak_flux
+                };
+                ratios.push(measured / (matched.flux * scale));
+
+                let ra_offset = (source.ra - matched.ra + 540.0).rem_euclid(360.0) - 180.0;
+                ra_offsets.push(ra_offset * matched.dec.to_radians().cos() * 3600.0);
+                dec_offsets.push((source.dec - matched.dec) * 3600.0);
+            }
+
+            CrossmatchSummary {
+                stokes: plane.stokes,
+                freq: plane.freq,
+                num_sources: plane_sources.len(),
+                num_matches: ratios.len(),
+                flux_ratio_scatter: calc_mad(&ratios),
+                median_flux_ratio: median(&mut ratios),
+                median_ra_offset: median(&mut ra_offsets),
+                median_dec_offset: median(&mut dec_offsets),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(ra: f64, dec: f64, peak_flux: f64, int_flux: f64) -> Source {
+        Source {
+            stokes: 1.0,
+            freq: 100e6,
+            island: 0,
+            x: 0.0,
+            y: 0.0,
+            ra,
+            dec,
+            peak_flux,
+            peak_snr: 10.0,
+            int_flux,
+            major: 1.0,
+            minor: 1.0,
+            pa: 0.0,
+            background: 0.0,
+            rms: 1.0,
+            num_pixels: 1,
+        }
+    }
+
+    fn reference(ra: f64, dec: f64, flux: f64, alpha: f64) -> ReferenceSource {
+        ReferenceSource {
+            ra,
+            dec,
+            flux,
+            alpha,
+        }
+    }
+
+    #[test]
+    fn matches_and_compares_sources() {
+        let counts = [SourceCounts {
+            stokes: 1.0,
+            freq: 100e6,
+            num_islands: 4,
+            num_detections: 4,
+            num_negative: 0,
+        }];
+        let sources = [
+            // Exact match, integrated flux over a reference doubled by alpha -1
+            source(10.0, -20.0, 5.0, 2.0),
+            // 5.4 arcsec east across RA 0 and 10 arcsec north, peak flux over
+            // a reference scaled by the default alpha
+            source(0.0005, 10.0 / 3600.0, 2f64.powf(0.7) * 1.5, f64::NAN),
+            // Nothing within the radius
+            source(50.0, 0.0, 1.0, 1.0),
+            // No position
+            source(f64::NAN, f64::NAN, 1.0, 1.0),
+        ];
+        let catalogue = [
+            reference(10.0, -20.0, 1.0, -1.0),
+            reference(359.999, 0.0, 1.0, f64::NAN),
+            reference(50.1, 0.0, 1.0, -0.7),
+        ];
+
+        let summary = crossmatch(&sources, &counts, &catalogue, 60.0 / 3600.0, 200e6, -0.7);
+        assert_eq!(summary.len(), 1);
+        let plane = &summary[0];
+        assert_eq!((plane.num_sources, plane.num_matches), (4, 2));
+        assert!((plane.median_flux_ratio - 1.25).abs() < 1e-9);
+        assert!((plane.median_ra_offset - 2.7).abs() < 1e-6);
+        assert!((plane.median_dec_offset - 5.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn planes_without_matches() {
+        let counts = [SourceCounts {
+            stokes: f64::NAN,
+            freq: f64::NAN,
+            num_islands: 0,
+            num_detections: 0,
+            num_negative: 0,
+        }];
+        let summary = crossmatch(&[], &counts, &[], 1.0, 200e6, -0.7);
+        assert_eq!((summary[0].num_sources, summary[0].num_matches), (0, 0));
+        assert!(summary[0].median_flux_ratio.is_nan());
+    }
+}
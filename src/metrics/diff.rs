@@ -0,0 +1,109 @@
+use crate::io::read::solutions::{CalSolFile, Solutions};
+use crate::metrics::bandpass::align_to;
+use crate::metrics::gain_amplitude::calculate_smoothness;
+use crate::metrics::gain_phase::{LinearRegression, unwrap_phases};
+use ndarray::prelude::*;
+use ndarray_stats::QuantileExt;
+use ndarray_stats::interpolate::Linear;
+use noisy_float::types::n64;
+use num_complex::Complex64;
+use std::error::Error;
+use std::path::Path;
+
+/// Per-tile comparison of two sets of solutions for the same observation
+#[derive(Debug)]
+pub(crate) struct SolutionDiff {
+    // XX and YY gains of A divided by B, on the tiles and channels of A, with
+    // XY and YX set to zero
+    pub(crate) ratio: Solutions,
+
+    // Median |A/B| of each tile, XX then YY
+    pub(crate) median_ratio: [Vec<f64>; 2],
+
+    // Circular mean of the A-B phase difference of each tile [rad]
+    pub(crate) mean_phase_diff: [Vec<f64>; 2],
+
+    // Gain smoothness of |A/B|
+    pub(crate) ratio_smoothness: [Vec<f64>; 2],
+
+    // RMSE of a straight-line fit to the unwrapped phase difference [rad]
+    pub(crate) phase_diff_rmse: [Vec<f64>; 2],
+}
+
+/// Align B to the tiles and channels of A and compare their gains
+pub(crate) fn run_diff_calc(path_a: &Path, path_b: &Path) -> Result<SolutionDiff, Box<dyn Error>> {
+    let solutions_a = CalSolFile {
+        file_path: path_a.to_path_buf(),
+    }
+    .read_fits()?;
+    let solutions_b = CalSolFile {
+        file_path: path_b.to_path_buf(),
+    }
+    .read_fits()?;
+
+    let gains_b = align_to(&solutions_b, &solutions_a).ok_or(format!(
+        "Channels of {} don't match {}",
+        path_b.display(),
+        path_a.display()
+    ))?;
+    let mut ratio_gains = &solutions_a.complex_gains / &gains_b;
+    for pol_index in [1, 2] {
+        ratio_gains
+            .slice_mut(s![.., .., pol_index])
+            .fill(Complex64::ZERO);
+    }
+    let channels = Array1::<f64>::range(0.0, solutions_a.num_chans as f64, 1.0);
+
+    let mut median_ratio = [vec![], vec![]];
+    let mut mean_phase_diff = [vec![], vec![]];
+    let mut ratio_smoothness = [vec![], vec![]];
+    let mut phase_diff_rmse = [vec![], vec![]];
+
+    for (pol, pol_index) in [0, 3].into_iter().enumerate() {
+        let ratios = ratio_gains.slice(s![.., .., pol_index]);
+
+        let mut amps = ratios.map(|c| c.norm());
+        median_ratio[pol] = amps
+            .quantile_axis_skipnan_mut(Axis(1), n64(0.5), &Linear)?
+            .to_vec();
+
+        for tile_ratios in ratios.axis_iter(Axis(0)) {
+            let valid: Vec<_> = tile_ratios.iter().filter(|c| !c.is_nan()).collect();
+            mean_phase_diff[pol].push(if valid.is_empty() {
+                f64::NAN
+            } else {
+                valid
+                    .iter()
+                    .map(|&&c| c / c.norm())
+                    .sum::<Complex64>()
+                    .arg()
+            });
+
+            let mut tile_amps = tile_ratios.map(|c| c.norm());
+            ratio_smoothness[pol].push(calculate_smoothness(&mut tile_amps)?);
+
+            let mut phases = tile_ratios.map(|c| c.arg());
+            unwrap_phases(&mut phases);
+            let mut fit = LinearRegression::new(channels.clone(), phases);
+            fit.fit();
+            phase_diff_rmse[pol].push(fit.calc_rmse());
+        }
+    }
+
+    let ratio = Solutions {
+        complex_gains: ratio_gains,
+        id: solutions_a.id,
+        num_tiles: solutions_a.num_tiles,
+        num_chans: solutions_a.num_chans,
+        chan_freqs: solutions_a.chan_freqs,
+        tile_names: solutions_a.tile_names,
+    };
+
+    Ok(SolutionDiff {
+        ratio,
+        median_ratio,
+        mean_phase_diff,
+        ratio_smoothness,
+        phase_diff_rmse,
+    })
+}
@@ -0,0 +1,70 @@
+use crate::io::read::solutions::CalSolFile;
+use itertools::Itertools;
+use ndarray::prelude::*;
+use std::error::Error;
+use std::path::Path;
+
+/// Obsid, per-tile and per-coarse-channel flagged fractions, flagged fraction
+/// of the whole observation and number of fully flagged tiles
+type FlagResults = (usize, Vec<f64>, Vec<f64>, f64, usize);
+
+/// Calculate flag occupancy from the NaNs hyperdrive puts in flagged solutions
+pub(crate) fn run_flag_occupancy_calc(file_path: &Path) -> Result<FlagResults, Box<dyn Error>> {
+    let file = CalSolFile {
+        file_path: file_path.to_path_buf(),
+    };
+    let solutions = file.read_fits()?;
+
+    // A chanblock counts as flagged if any of its polarisations are NaN
+    let flagged = solutions
+        .complex_gains
+        .map_axis(Axis(2), |pols| pols.iter().any(|c| c.is_nan()));
+
+    let tile_fractions = flagged
+        .map_axis(Axis(1), |chans| flag_fraction(chans.iter()))
+        .to_vec();
+
+    let flagged_tiles: Vec<usize> = tile_fractions.iter().positions(|&f| f == 1.0).collect();
+
+    // Coarse channel occupancy ignores fully flagged tiles, so that it shows
+    // channels flagged on otherwise good tiles
+    let coarse_chans = solutions.coarse_chans();
+    let num_coarse_chans = coarse_chans.iter().max().map_or(0, |c| c + 1);
+    let coarse_fractions = (0..num_coarse_chans)
+        .map(|coarse| {
+            flag_fraction(
+                flagged
+                    .axis_iter(Axis(0))
+                    .enumerate()
+                    .filter(|(tile, _)| !flagged_tiles.contains(tile))
+                    .flat_map(|(_, chans)| {
+                        chans
+                            .into_iter()
+                            .zip(&coarse_chans)
+                            .filter(|(_, c)| **c == coarse)
+                            .map(|(flag, _)| flag)
+                    }),
+            )
+        })
+        .collect();
+
+    let num_flagged = flagged.iter().filter(|&&f| f).count();
+    let obs_fraction = num_flagged as f64 / (solutions.num_tiles * solutions.num_chans) as f64;
+
+    Ok((
+        solutions.id,
+        tile_fractions,
+        coarse_fractions,
+        obs_fraction,
+        flagged_tiles.len(),
+    ))
+}
+
+fn flag_fraction<'a>(flags: impl Iterator<Item = &'a bool>) -> f64 {
+    let (num_flagged, num_total) = flags.fold((0, 0), |(n, t), &f| (n + f as usize, t + 1));
+    if num_total == 0 {
+        f64::NAN
+    } else {
+        num_flagged as f64 / num_total as f64
+    }
+}
@@ -1,3 +1,4 @@
+use crate::io::read::metafits::{Metafits, NUM_DIPOLES};
 use crate::io::read::solutions::CalSolFile;
 use crate::metrics::interp::InterpolateNans;
 use ndarray::{Zip, prelude::*};
@@ -9,17 +10,95 @@ use num_complex::Complex64;
 use rayon::iter::IntoParallelIterator;
 use rayon::prelude::*;
 use std::error::Error;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-/// Wrapper around the actual smoothnes calculation
+/// Obsid with the XX and YY smoothness of each tile
+type SmoothnessResults = (usize, Vec<f64>, Vec<f64>);
+
+/// Median XX and YY amplitude bandpass over every tile of a set of observations
+#[derive(Debug)]
+pub(crate) struct ReferenceBandpass {
+    pub(crate) xx: Array1<f64>,
+    pub(crate) yy: Array1<f64>,
+}
+
+/// Build a reference bandpass from all tiles in all of the given solutions.
+/// Files with a different number of channels than the first are skipped.
+pub(crate) fn build_reference_bandpass(
+    paths: &[PathBuf],
+) -> Result<ReferenceBandpass, Box<dyn Error>> {
+    let mut xx_gains: Vec<Array2<f64>> = vec![];
+    let mut yy_gains: Vec<Array2<f64>> = vec![];
+
+    for path in paths {
+        let file = CalSolFile {
+            file_path: path.to_path_buf(),
+        };
+        let Ok(solutions) = file.read_fits() else {
+            eprintln!("Warning: unable to read {}", path.display());
+            continue;
+        };
+
+        if let Some(first) = xx_gains.first()
+            && first.ncols() != solutions.num_chans
+        {
+            eprintln!(
+                "Warning: obsid {} has a different number of channels, not using it as a reference",
+                solutions.id
+            );
+            continue;
+        }
+
+        let gains = &solutions.complex_gains;
+        xx_gains.push(gains.slice(s![.., .., 0]).map(|c| c.norm()));
+        yy_gains.push(gains.slice(s![.., .., 3]).map(|c| c.norm()));
+    }
+
+    if xx_gains.is_empty() {
+        return Err("No reference solutions could be read".into());
+    }
+
+    // Stack the tiles of every observation, then take the median over them
+    let median = |gains: &[Array2<f64>]| -> Result<Array1<f64>, Box<dyn Error>> {
+        let views: Vec<_> = gains.iter().map(|g| g.view()).collect();
+        Ok(
+            ndarray::concatenate(Axis(0), &views)?.quantile_axis_skipnan_mut(
+                Axis(0),
+                n64(0.5),
+                &Linear,
+            )?,
+        )
+    };
+
+    Ok(ReferenceBandpass {
+        xx: median(&xx_gains)?,
+        yy: median(&yy_gains)?,
+    })
+}
+
+/// Wrapper around the actual smoothnes calculation. Tiles are normalised by
+/// `reference` if given, otherwise by the median over tiles in this file.
 pub(crate) fn run_smoothness_calc(
     file_path: &Path,
-) -> Result<(usize, Vec<f64>, Vec<f64>), Box<dyn Error>> {
+    reference: Option<&ReferenceBandpass>,
+) -> Result<SmoothnessResults, Box<dyn Error>> {
     let file = CalSolFile {
         file_path: file_path.to_path_buf(),
     };
     let solutions = file.read_fits()?;
 
+    if let Some(reference) = reference
+        && reference.xx.len() != solutions.num_chans
+    {
+        return Err(format!(
+            "Obsid {} has {} channels but the reference bandpass has {}",
+            solutions.id,
+            solutions.num_chans,
+            reference.xx.len()
+        )
+        .into());
+    }
+
     let mut all_xx_gains = solutions
         .complex_gains
         .slice(s![.., .., 0])
@@ -29,15 +108,18 @@ pub(crate) fn run_smoothness_calc(
         .slice(s![.., .., 3])
         .map(|c| c.norm());
 
-    // Need to clone since quantile_axis_skipnan_mut mutates arrays in place.
-    let median_xx_gains =
-        all_xx_gains
-            .clone()
-            .quantile_axis_skipnan_mut(Axis(0), n64(0.5), &Linear)?;
-    let median_yy_gains =
-        all_yy_gains
-            .clone()
-            .quantile_axis_skipnan_mut(Axis(0), n64(0.5), &Linear)?;
+    let (median_xx_gains, median_yy_gains) = match reference {
+        Some(reference) => (reference.xx.clone(), reference.yy.clone()),
+        None => (
+            // Need to clone since quantile_axis_skipnan_mut mutates arrays in place.
+            all_xx_gains
+                .clone()
+                .quantile_axis_skipnan_mut(Axis(0), n64(0.5), &Linear)?,
+            all_yy_gains
+                .clone()
+                .quantile_axis_skipnan_mut(Axis(0), n64(0.5), &Linear)?,
+        ),
+    };
 
     // let (xx_smoothness_vec, yy_smoothness_vec) = all_xx_gains
     //     .axis_iter_mut(Axis(0))
@@ -57,8 +139,12 @@ pub(crate) fn run_smoothness_calc(
         Zip::from(all_xx_gains.axis_iter_mut(Axis(0)))
             .and(all_yy_gains.axis_iter_mut(Axis(0)))
             .into_par_iter()
-            .filter(|(xx, _)| !xx.is_all_nan())
             .map(|(mut xx, mut yy)| {
+                // Flagged tiles are all NaN. Keep them as NaN so that the
+                // results stay indexed by tile.
+                if xx.iter().all(|x| x.is_nan()) {
+                    return (f64::NAN, f64::NAN);
+                }
                 xx.zip_mut_with(&median_xx_gains, |x, &y| *x /= y);
                 yy.zip_mut_with(&median_yy_gains, |y, &z| *y /= z);
                 (
@@ -72,16 +158,107 @@ pub(crate) fn run_smoothness_calc(
 }
 
 /// Caluclate gain smoothness with the FT
-fn calculate_smoothness(gains: &mut Array1<f64>) -> Result<f64, Box<dyn Error>> {
+pub(crate) fn calculate_smoothness(gains: &mut Array1<f64>) -> Result<f64, Box<dyn Error>> {
     gains.interp_nans_inplace();
     let num_chans = gains.len();
 
-    let mut complex_gains = Array1::from_iter(gains.iter().map(|&g| Complex64::new(g, 0.0)));
+    let complex_gains = Array1::from_iter(gains.iter().map(|&g| Complex64::new(g, 0.0)));
     let mut output = Array1::<Complex64>::zeros(num_chans);
-    let mut handler = FftHandler::new(num_chans);
+    let handler = FftHandler::new(num_chans);
 
-    ndfft(&mut complex_gains, &mut output, &mut handler, 0);
+    ndfft(&complex_gains, &mut output, &handler, 0);
 
     let smooth_array = output.slice(s![1..num_chans / 2]).mapv(|x| x.norm()) / output[0].norm();
     Ok(smooth_array.mean().expect("Unable to calculate smoothness"))
 }
+
+/// Per-tile median |XX|/|YY| across frequency, normalised by the array median
+pub(crate) fn run_amp_ratio_calc(file_path: &Path) -> Result<(usize, Vec<f64>), Box<dyn Error>> {
+    let file = CalSolFile {
+        file_path: file_path.to_path_buf(),
+    };
+    let solutions = file.read_fits()?;
+
+    let mut ratios = Zip::from(solutions.complex_gains.slice(s![.., .., 0]))
+        .and(solutions.complex_gains.slice(s![.., .., 3]))
+        .map_collect(|xx, yy| xx.norm() / yy.norm());
+
+    // Flagged tiles are all NaN and stay NaN
+    let mut tile_ratios = ratios.quantile_axis_skipnan_mut(Axis(1), n64(0.5), &Linear)?;
+    let array_ratio = tile_ratios
+        .clone()
+        .quantile_axis_skipnan_mut(Axis(0), n64(0.5), &Linear)?
+        .into_scalar();
+    tile_ratios /= array_ratio;
+
+    Ok((solutions.id, tile_ratios.to_vec()))
+}
+
+/// A tile with a deviant XX/YY ratio, or with dead dipoles in the metafits
+#[derive(Debug)]
+pub(crate) struct RatioFlag {
+    pub(crate) tile: usize,
+
+    // Normalised XX/YY amplitude ratio
+    pub(crate) ratio: f64,
+
+    // Dead X and Y dipoles according to the metafits, if supplied
+    pub(crate) dead_dipoles: Option<[usize; 2]>,
+
+    // Ratio expected from the dead dipoles alone
+    pub(crate) expected_ratio: Option<f64>,
+
+    // "flagged" without a metafits. With one, "explained" if the dead dipoles
+    // account for the deviation, "unexplained" if they don't, and "undetected"
+    // for dead dipoles that didn't produce a deviation.
+    pub(crate) status: &'static str,
+}
+
+/// Flag tiles whose normalised XX/YY ratio deviates from 1 by more than
+/// `threshold`, cross-checking them against the metafits dead dipoles
+pub(crate) fn flag_amp_ratios(
+    ratios: &[f64],
+    metafits: Option<&Metafits>,
+    threshold: f64,
+) -> Vec<RatioFlag> {
+    ratios
+        .iter()
+        .enumerate()
+        .filter_map(|(tile, &ratio)| {
+            let deviant = (ratio - 1.0).abs() > threshold;
+
+            let Some(tile_info) = metafits.and_then(|m| m.tiles.get(tile)) else {
+                return deviant.then_some(RatioFlag {
+                    tile,
+                    ratio,
+                    dead_dipoles: None,
+                    expected_ratio: None,
+                    status: "flagged",
+                });
+            };
+
+            // Gain scales with the number of live dipoles in each polarisation
+            let [dead_x, dead_y] = tile_info.dead_dipoles;
+            let expected_ratio =
+                (NUM_DIPOLES - dead_x) as f64 / (NUM_DIPOLES - dead_y).max(1) as f64;
+            let expected_deviant = (expected_ratio - 1.0).abs() > f64::EPSILON;
+
+            let status = match (deviant, expected_deviant) {
+                (true, true) if (ratio - 1.0).signum() == (expected_ratio - 1.0).signum() => {
+                    "explained"
+                }
+                (true, _) => "unexplained",
+                (false, true) if !ratio.is_nan() => "undetected",
+                _ => return None,
+            };
+
+            Some(RatioFlag {
+                tile,
+                ratio,
+                dead_dipoles: Some(tile_info.dead_dipoles),
+                expected_ratio: Some(expected_ratio),
+                status,
+            })
+        })
+        .collect()
+}
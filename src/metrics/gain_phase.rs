@@ -3,11 +3,14 @@ use crate::metrics::interp::InterpolateNans;
 use itertools::Itertools;
 use ndarray::prelude::*;
 use std::error::Error;
+use std::f64::consts::PI;
 use std::path::Path;
 
-pub(crate) fn run_phase_calcs(
-    file_path: &Path,
-) -> Result<(usize, Vec<f64>, Vec<f64>, Vec<f64>), Box<dyn Error>> {
+/// Obsid with the XX-YY phase difference mean and RMS, XX RMSE and YY RMSE of
+/// each tile
+type PhaseResults = (usize, Vec<f64>, Vec<f64>, Vec<f64>, Vec<f64>);
+
+pub(crate) fn run_phase_calcs(file_path: &Path) -> Result<PhaseResults, Box<dyn Error>> {
     let file = CalSolFile {
         file_path: file_path.to_path_buf(),
     };
@@ -23,29 +26,34 @@ pub(crate) fn run_phase_calcs(
         .slice(s![.., .., 3])
         .map(|c| c.arg());
 
-    let (dist_vec, xx_rmse_vec, yy_rmse_vec): (Vec<_>, Vec<_>, Vec<_>) = all_xx_angs
-        .axis_iter(Axis(0))
-        .zip(all_yy_angs.axis_iter(Axis(0)))
-        .map(|(xx_angs, yy_angs)| {
-            let mut x_fit = LinearRegression::new(channels.clone(), xx_angs.to_owned());
-            x_fit.fit();
-            let mut y_fit = LinearRegression::new(channels.clone(), yy_angs.to_owned());
-            y_fit.fit();
-
-            (
-                calc_dist(&xx_angs, &yy_angs),
-                x_fit.calc_rmse(),
-                y_fit.calc_rmse(),
-            )
-        })
-        .collect::<Vec<_>>()
-        .into_iter()
-        .multiunzip();
-
-    Ok((solutions.id, dist_vec, xx_rmse_vec, yy_rmse_vec))
+    let (diff_mean_vec, diff_rms_vec, xx_rmse_vec, yy_rmse_vec): (Vec<_>, Vec<_>, Vec<_>, Vec<_>) =
+        all_xx_angs
+            .axis_iter(Axis(0))
+            .zip(all_yy_angs.axis_iter(Axis(0)))
+            .map(|(xx_angs, yy_angs)| {
+                let mut x_fit = LinearRegression::new(channels.clone(), xx_angs.to_owned());
+                x_fit.fit();
+                let mut y_fit = LinearRegression::new(channels.clone(), yy_angs.to_owned());
+                y_fit.fit();
+
+                let (diff_mean, diff_rms) = calc_phase_diff(&xx_angs, &yy_angs, &channels);
+
+                (diff_mean, diff_rms, x_fit.calc_rmse(), y_fit.calc_rmse())
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .multiunzip();
+
+    Ok((
+        solutions.id,
+        diff_mean_vec,
+        diff_rms_vec,
+        xx_rmse_vec,
+        yy_rmse_vec,
+    ))
 }
 
-struct LinearRegression {
+pub(crate) struct LinearRegression {
     x: Array1<f64>,
     y: Array1<f64>,
     pub gradient: Option<f64>,
@@ -54,18 +62,18 @@ struct LinearRegression {
 
 impl LinearRegression {
     /// Create new LinearRegression object
-    fn new(x: Array1<f64>, y: Array1<f64>) -> Self {
+    pub(crate) fn new(x: Array1<f64>, y: Array1<f64>) -> Self {
         assert_eq!(x.len(), y.len());
         Self {
-            x: x,
-            y: y,
+            x,
+            y,
             gradient: None,
             intercept: None,
         }
     }
 
     /// Fit data and modify LinearRegression object
-    fn fit(&mut self) {
+    pub(crate) fn fit(&mut self) {
         let n = self.x.len() as f64;
 
         if self.y.is_any_nan() {
@@ -85,7 +93,7 @@ impl LinearRegression {
     }
 
     /// Calculat RMSE
-    fn calc_rmse(&self) -> f64 {
+    pub(crate) fn calc_rmse(&self) -> f64 {
         let yy = &self.x * self.gradient.unwrap() + self.intercept.unwrap();
         ((&self.y - &yy).powi(2))
             .mean()
@@ -94,11 +102,98 @@ impl LinearRegression {
     }
 }
 
-fn calc_dist(pol1: &ArrayView1<f64>, pol2: &ArrayView1<f64>) -> f64 {
-    // shift pol1 to start at pol2
-    let shifted = pol1 - (pol1[0] - pol2[0]);
-    (shifted - pol2)
-        .mean()
-        .expect("Unable to calculate mean in average euclidean distance")
-        .abs()
+/// Circular mean of the XX-YY phase difference, i.e. the phase offset between
+/// the polarisations, and the RMS of the difference after removing a
+/// straight-line fit from each polarisation's unwrapped phase, i.e. how much
+/// their shapes differ. Differences are wrapped to [-pi, pi] and flagged
+/// channels are ignored.
+fn calc_phase_diff(
+    xx_angs: &ArrayView1<f64>,
+    yy_angs: &ArrayView1<f64>,
+    channels: &Array1<f64>,
+) -> (f64, f64) {
+    let wrapped = |diffs: Array1<f64>| -> Vec<f64> {
+        diffs
+            .iter()
+            .filter(|d| !d.is_nan())
+            .map(|d| d.sin().atan2(d.cos()))
+            .collect()
+    };
+
+    // The residual differences have a mean of about 0 by construction, so the
+    // mean is taken before removing the fits
+    let diffs = wrapped(xx_angs - yy_angs);
+    let residual_diffs =
+        wrapped(fit_residuals(xx_angs, channels) - fit_residuals(yy_angs, channels));
+
+    if diffs.is_empty() {
+        return (f64::NAN, f64::NAN);
+    }
+
+    let mean = diffs
+        .iter()
+        .map(|d| d.sin())
+        .sum::<f64>()
+        .atan2(diffs.iter().map(|d| d.cos()).sum::<f64>());
+    let rms =
+        (residual_diffs.iter().map(|d| d * d).sum::<f64>() / residual_diffs.len() as f64).sqrt();
+
+    (mean, rms)
+}
+
+/// Unwrapped phase minus its straight-line fit, NaN where the phase is NaN
+pub(crate) fn fit_residuals(angs: &ArrayView1<f64>, channels: &Array1<f64>) -> Array1<f64> {
+    let mut unwrapped = angs.to_owned();
+    unwrap_phases(&mut unwrapped);
+
+    let mut fit = LinearRegression::new(channels.clone(), unwrapped.clone());
+    fit.fit();
+
+    unwrapped - (channels * fit.gradient.unwrap() + fit.intercept.unwrap())
+}
+
+/// Unwrap phases along frequency, skipping NaNs
+pub(crate) fn unwrap_phases(phases: &mut Array1<f64>) {
+    let mut offset = 0.0;
+    let mut prev: Option<f64> = None;
+
+    for phase in phases.iter_mut().filter(|p| !p.is_nan()) {
+        let wrapped = *phase;
+        if let Some(prev) = prev {
+            let diff = wrapped - prev;
+            offset -= 2.0 * PI * (diff / (2.0 * PI)).round();
+        }
+        prev = Some(wrapped);
+        *phase = wrapped + offset;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phase_diff_of_offset_and_ripple() {
+        let channels = Array1::range(0.0, 64.0, 1.0);
+
+        // XX leads YY by 0.5 rad and wraps, with a ripple on top
+        let xx = channels.mapv(|c: f64| {
+            let phase = 0.5 + 0.2 * c + 0.05 * (c * 0.7).sin();
+            phase.sin().atan2(phase.cos())
+        });
+        let yy = channels.mapv(|c: f64| {
+            let phase = 0.2 * c;
+            phase.sin().atan2(phase.cos())
+        });
+        let mut yy_flagged = yy.clone();
+        yy_flagged[10] = f64::NAN;
+
+        let (mean, rms) = calc_phase_diff(&xx.view(), &yy_flagged.view(), &channels);
+        assert!((mean - 0.5).abs() < 0.01);
+        assert!(rms > 0.02 && rms < 0.05);
+
+        // No ripple, no difference in shape
+        let (mean, rms) = calc_phase_diff(&yy.view(), &yy.view(), &channels);
+        assert!(mean.abs() < 1e-12 && rms < 1e-9);
+    }
 }
@@ -0,0 +1,114 @@
+use crate::io::read::metafits::{Metafits, TileInfo};
+use clap::ValueEnum;
+use ndarray::prelude::*;
+use ndarray_stats::QuantileExt;
+use ndarray_stats::interpolate::Linear;
+use noisy_float::types::n64;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// Hardware properties that tiles can be grouped by
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub(crate) enum TileGrouping {
+    /// Receiver the tile is plugged into
+    Receiver,
+    /// Cable type and length class
+    Cable,
+    /// Hex, long baseline or standard tile
+    Config,
+}
+
+impl TileGrouping {
+    /// Name used in output file names
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            TileGrouping::Receiver => "receiver",
+            TileGrouping::Cable => "cable",
+            TileGrouping::Config => "config",
+        }
+    }
+
+    fn label(&self, tile: &TileInfo) -> Option<String> {
+        match self {
+            TileGrouping::Receiver => Some(format!("Rx{:02}", tile.receiver)),
+            TileGrouping::Cable => tile.cable_flavour.clone(),
+            TileGrouping::Config => {
+                let config = if tile.name.starts_with("Hex") {
+                    "hex"
+                } else if tile.name.starts_with("LB") {
+                    "long_baseline"
+                } else {
+                    "standard"
+                };
+                Some(config.to_string())
+            }
+        }
+    }
+}
+
+/// Summary of a per-tile metric over one group of tiles
+#[derive(Debug)]
+pub(crate) struct GroupStats {
+    pub(crate) label: String,
+
+    // Number of tiles in the group with a valid (non-NaN) value
+    pub(crate) num_tiles: usize,
+
+    pub(crate) mean: f64,
+
+    pub(crate) median: f64,
+
+    // Largest value in the group, since larger is worse for all tile metrics
+    pub(crate) worst: f64,
+}
+
+/// Aggregate a per-tile metric, indexed by antenna, into per-group statistics
+pub(crate) fn group_tile_metric(
+    values: &[f64],
+    metafits: &Metafits,
+    grouping: TileGrouping,
+) -> Result<Vec<GroupStats>, Box<dyn Error>> {
+    if values.len() != metafits.tiles.len() {
+        return Err(format!(
+            "Metric has {} tiles but metafits for obsid {} has {}",
+            values.len(),
+            metafits.id,
+            metafits.tiles.len()
+        )
+        .into());
+    }
+
+    let mut groups: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    for (tile, &value) in metafits.tiles.iter().zip(values) {
+        let label = grouping.label(tile).ok_or(format!(
+            "Metafits for obsid {} has no cable information",
+            metafits.id
+        ))?;
+        groups.entry(label).or_default().push(value);
+    }
+
+    groups
+        .into_iter()
+        .map(|(label, group)| {
+            let mut valid = Array1::from_iter(group.into_iter().filter(|v| !v.is_nan()));
+            let num_tiles = valid.len();
+            let mean = valid.mean().unwrap_or(f64::NAN);
+            let worst = valid.iter().copied().fold(f64::NAN, f64::max);
+            let median = if num_tiles > 0 {
+                valid
+                    .quantile_axis_skipnan_mut(Axis(0), n64(0.5), &Linear)?
+                    .into_scalar()
+            } else {
+                f64::NAN
+            };
+
+            Ok(GroupStats {
+                label,
+                num_tiles,
+                mean,
+                median,
+                worst,
+            })
+        })
+        .collect()
+}
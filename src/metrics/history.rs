@@ -0,0 +1,84 @@
+use crate::io::read::history::History;
+
+/// Fewest previous values needed for a meaningful z-score
+const MIN_HISTORY: usize = 3;
+
+/// Z-score of `value` against the history of `metric` for the tile named
+/// `tile`, or for the whole observation if `tile` is None. Any previous value
+/// for the same obsid is left out, so re-running an observation doesn't score
+/// it against itself. NaN if there is too little history or it has no spread.
+pub(crate) fn z_score(
+    history: &History,
+    metric: &str,
+    tile: Option<&str>,
+    obsid: usize,
+    value: f64,
+) -> f64 {
+    let Some(previous) = history
+        .values
+        .get(&(metric.to_string(), tile.map(str::to_string)))
+    else {
+        return f64::NAN;
+    };
+
+    let previous: Vec<f64> = previous
+        .iter()
+        .filter(|&(&id, v)| id != obsid && !v.is_nan())
+        .map(|(_, &v)| v)
+        .collect();
+    if previous.len() < MIN_HISTORY {
+        return f64::NAN;
+    }
+
+    let num = previous.len() as f64;
+    let mean = previous.iter().sum::<f64>() / num;
+    let std_dev = (previous.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (num - 1.0)).sqrt();
+    if std_dev == 0.0 {
+        return f64::NAN;
+    }
+
+    (value - mean) / std_dev
+}
+
+/// Add a value to the history, replacing any previous value for the same
+/// metric, tile and obsid. NaNs, e.g. from flagged tiles, aren't recorded.
+pub(crate) fn record(
+    history: &mut History,
+    metric: &str,
+    tile: Option<&str>,
+    obsid: usize,
+    value: f64,
+) {
+    if value.is_nan() {
+        return;
+    }
+    history
+        .values
+        .entry((metric.to_string(), tile.map(str::to_string)))
+        .or_default()
+        .insert(obsid, value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scores_tiles_by_name() {
+        let mut history = History::default();
+        for (obsid, value) in [(1, 1.0), (2, 2.0), (3, 3.0)] {
+            record(&mut history, "metric", Some("Tile011"), obsid, value);
+            record(&mut history, "metric", Some("Tile012"), obsid, 10.0 * value);
+        }
+        record(&mut history, "metric", None, 1, f64::NAN);
+
+        // Mean 2 and standard deviation 1 for Tile011, 20 and 10 for Tile012
+        assert_eq!(z_score(&history, "metric", Some("Tile011"), 4, 4.0), 2.0);
+        assert_eq!(z_score(&history, "metric", Some("Tile012"), 4, 10.0), -1.0);
+        assert!(z_score(&history, "metric", Some("Tile013"), 4, 1.0).is_nan());
+        assert!(z_score(&history, "metric", None, 4, 1.0).is_nan());
+
+        // The observation's own value is left out, leaving too little history
+        assert!(z_score(&history, "metric", Some("Tile011"), 3, 4.0).is_nan());
+    }
+}
@@ -1,42 +1,506 @@
-use crate::io::read::image::ImageFile;
+use crate::io::read::image::{Image, ImageFile};
+use crate::io::read::wcs::angular_distance;
+use crate::metrics::region::PixelSelection;
+use clap::ValueEnum;
 use ndarray::prelude::*;
-use ndarray_stats::QuantileExt;
-use num_complex::ComplexFloat;
-use num_traits::Float;
-use rayon::iter::IntoParallelRefIterator;
 use rayon::prelude::*;
-use std::{error::Error, path::Path};
+use regex::Regex;
+use std::error::Error;
+use std::path::{Path, PathBuf};
 
-pub(crate) fn run_image_calc(path: &Path) -> Result<(usize, f64, f64), Box<dyn Error>> {
-    let file = ImageFile {
-        file_path: path.to_path_buf(),
+/// Number of standard deviations beyond which sigma clipping rejects pixels
+const CLIP_SIGMA: f64 = 3.0;
+
+/// Maximum number of sigma clipping iterations
+const MAX_CLIP_ITERATIONS: usize = 10;
+
+/// Ratio of the standard deviation to the MAD for Gaussian noise
+const MAD_TO_SIGMA: f64 = 1.4826;
+
+/// Ways of estimating the noise of an image plane
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub(crate) enum NoiseEstimator {
+    /// RMS of every pixel, including sources
+    Rms,
+    /// Standard deviation after iterative 3 sigma clipping about the median
+    SigmaClip,
+    /// Median absolute deviation from the median, scaled to a Gaussian sigma
+    Mad,
+    /// RMS of the negative pixels only, which sources don't contribute to
+    Negative,
+}
+
+impl NoiseEstimator {
+    /// Name used for output columns
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            NoiseEstimator::Rms => "rms",
+            NoiseEstimator::SigmaClip => "sigma_clip",
+            NoiseEstimator::Mad => "mad",
+            NoiseEstimator::Negative => "negative_rms",
+        }
+    }
+
+    /// Noise of the valid pixels of a plane
+    fn estimate(&self, valid: &[f64]) -> f64 {
+        match self {
+            NoiseEstimator::Rms => calc_rms(valid),
+            NoiseEstimator::SigmaClip => calc_sigma_clip(valid),
+            NoiseEstimator::Mad => calc_mad(valid),
+            NoiseEstimator::Negative => calc_negative_rms(valid),
+        }
+    }
+}
+
+/// Suffixes of the restored images WSClean writes, without and with primary
+/// beam correction. The residual and model images replace "image" with
+/// "residual" and "model".
+const WSCLEAN_IMAGE_SUFFIXES: [&str; 2] = ["-image.fits", "-image-pb.fits"];
+
+/// Residual and model images that go with a restored image
+#[derive(Debug, Default)]
+pub(crate) struct ImageSet {
+    pub(crate) residual: Option<PathBuf>,
+    pub(crate) model: Option<PathBuf>,
+}
+
+/// Find the residual and model images WSClean writes next to a restored
+/// image, e.g. obs-MFS-residual.fits for obs-MFS-image.fits
+pub(crate) fn find_wsclean_set(path: &Path) -> ImageSet {
+    let companion = |kind: &str| {
+        let name = path.file_name()?.to_str()?;
+        let suffix = WSCLEAN_IMAGE_SUFFIXES
+            .iter()
+            .find(|suffix| name.ends_with(*suffix))?;
+        let prefix = name.strip_suffix(suffix)?;
+        let companion = path.with_file_name(format!("{}{}", prefix, suffix.replace("image", kind)));
+        companion.is_file().then_some(companion)
     };
 
-    let image = file.read_fits()?;
-    let rms = calc_rms(&image.data)?;
-    let max = calc_max(&image.data)?;
-    let dr = calc_dr(max, rms);
-    Ok((image.id, rms, dr))
+    ImageSet {
+        residual: companion("residual"),
+        model: companion("model"),
+    }
+}
+
+/// Restored image that a WSClean residual or model image belongs to, if
+/// `path` is named like one
+pub(crate) fn wsclean_restored_image(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    WSCLEAN_IMAGE_SUFFIXES.iter().find_map(|suffix| {
+        ["residual", "model"].iter().find_map(|kind| {
+            let prefix = name.strip_suffix(&suffix.replace("image", kind))?;
+            Some(path.with_file_name(format!("{}{}", prefix, suffix)))
+        })
+    })
 }
 
-fn calc_rms(data: &Array2<f64>) -> Result<f64, Box<dyn Error>> {
-    let result;
-    let num_pixels = data.len();
-    if num_pixels < 1e6 as usize {
-        result = data.powi(2).mean().unwrap().sqrt();
+/// Image metrics of a single Stokes and frequency plane
+#[derive(Debug)]
+pub(crate) struct PlaneMetrics {
+    // FITS Stokes code, NaN if the image has no Stokes axis
+    pub(crate) stokes: f64,
+
+    // Frequency [Hz], NaN if the image has no frequency axis
+    pub(crate) freq: f64,
+
+    // Number of selected pixels of the image that aren't NaN or BLANK, which
+    // the peak is found from
+    pub(crate) num_valid_pixels: usize,
+
+    // Number of pixels the noise is estimated from, which are those of the
+    // residual image if there is one and are limited by any noise radius
+    pub(crate) num_noise_pixels: usize,
+
+    // Whether the noise is from the residual image rather than the image
+    pub(crate) noise_from_residual: bool,
+
+    // Noise from each of the requested estimators
+    pub(crate) noise: Vec<f64>,
+
+    // Peak of the restored image
+    pub(crate) peak: f64,
+
+    // RA and Dec of the peak [deg], NaN if the image has no WCS
+    pub(crate) peak_ra: f64,
+    pub(crate) peak_dec: f64,
+
+    // Peak divided by each noise estimate
+    pub(crate) dr: Vec<f64>,
+
+    // Largest absolute residual, NaN without a residual image
+    pub(crate) residual_peak: f64,
+
+    // Sum of the clean components, NaN without a model image
+    pub(crate) model_flux: f64,
+}
+
+/// Obsid with the noise and dynamic range of every plane of an image, using
+/// each of `estimators`.
+///
+/// With a residual image, the dynamic range is the peak of the restored image
+/// divided by the noise of the residual, otherwise the noise of the image
+/// itself is used. Every metric only uses the pixels in `selection`. With
+/// `noise_radius` [deg], only those within that distance of the phase centre
+/// count towards the noise.
+pub(crate) fn run_image_calc(
+    path: &Path,
+    set: &ImageSet,
+    obsid_pattern: &Regex,
+    estimators: &[NoiseEstimator],
+    noise_radius: Option<f64>,
+    selection: &PixelSelection,
+) -> Result<(usize, Vec<PlaneMetrics>), Box<dyn Error>> {
+    let read = |path: &Path| {
+        ImageFile {
+            file_path: path.to_path_buf(),
+            obsid_pattern: obsid_pattern.clone(),
+        }
+        .read_fits()
+    };
+
+    let image = read(path)?;
+    let residual = set.residual.as_deref().map(read).transpose()?;
+    let model = set.model.as_deref().map(read).transpose()?;
+    for (companion, companion_path) in [(&residual, &set.residual), (&model, &set.model)] {
+        if let (Some(companion), Some(companion_path)) = (companion, companion_path)
+            && companion.data.dim() != image.data.dim()
+        {
+            return Err(format!(
+                "{} has a different shape to {}",
+                companion_path.display(),
+                path.display()
+            )
+            .into());
+        }
+    }
+
+    let mask = selection.pixel_mask(&image)?;
+    let noise_mask = match noise_radius {
+        Some(radius) => {
+            let mut noise_mask = radius_mask(&image, radius).ok_or_else(|| {
+                format!(
+                    "{} has no celestial WCS to find pixels within {} deg of the phase centre",
+                    path.display(),
+                    radius
+                )
+            })?;
+            if let Some(mask) = &mask {
+                noise_mask.zip_mut_with(mask, |n, &m| *n &= m);
+            }
+            Some(noise_mask)
+        }
+        None => mask.clone(),
+    };
+
+    // BLANK pixels are NaN by now. All NaN planes give NaN metrics.
+    let valid_pixels = |plane: ArrayView2<f64>, mask: Option<&Array2<bool>>| -> Vec<f64> {
+        match mask {
+            Some(mask) => plane
+                .iter()
+                .zip(mask)
+                .filter(|&(v, &inside)| inside && v.is_finite())
+                .map(|(&v, _)| v)
+                .collect(),
+            None => plane.iter().copied().filter(|v| v.is_finite()).collect(),
+        }
+    };
+    let plane_of = |data: &Array4<f64>, stokes: usize, freq: usize, mask| {
+        valid_pixels(data.slice(s![stokes, freq, .., ..]), mask)
+    };
+
+    let (num_stokes, num_freqs, _, _) = image.data.dim();
+    let mut planes = vec![];
+    for stokes_index in 0..num_stokes {
+        for freq_index in 0..num_freqs {
+            let valid = plane_of(&image.data, stokes_index, freq_index, mask.as_ref());
+            let residual_valid = residual
+                .as_ref()
+                .map(|r| plane_of(&r.data, stokes_index, freq_index, mask.as_ref()));
+            let model_valid = model
+                .as_ref()
+                .map(|m| plane_of(&m.data, stokes_index, freq_index, mask.as_ref()));
+            let noise_pixels = plane_of(
+                &residual.as_ref().unwrap_or(&image).data,
+                stokes_index,
+                freq_index,
+                noise_mask.as_ref(),
+            );
+
+            let peak = calc_max(&valid);
+            let (peak_ra, peak_dec) =
+                peak_position(&image, stokes_index, freq_index, mask.as_ref())
+                    .unwrap_or((f64::NAN, f64::NAN));
+            let noise: Vec<f64> = estimators
+                .iter()
+                .map(|e| e.estimate(&noise_pixels))
+                .collect();
+            planes.push(PlaneMetrics {
+                stokes: image.stokes[stokes_index],
+                freq: image.freqs[freq_index],
+                num_valid_pixels: valid.len(),
+                num_noise_pixels: noise_pixels.len(),
+                noise_from_residual: residual_valid.is_some(),
+                dr: noise.iter().map(|&n| calc_dr(peak, n)).collect(),
+                noise,
+                peak,
+                peak_ra,
+                peak_dec,
+                residual_peak: residual_valid.map_or(f64::NAN, |r| {
+                    r.iter().map(|v| v.abs()).fold(f64::NAN, f64::max)
+                }),
+                model_flux: model_valid.map_or(f64::NAN, |m| m.iter().sum()),
+            });
+        }
+    }
+
+    Ok((image.id, planes))
+}
+
+/// Pixels within `radius` [deg] of the phase centre, None without a WCS
+fn radius_mask(image: &Image, radius: f64) -> Option<Array2<bool>> {
+    let wcs = image.wcs.as_ref()?;
+    let [ra0, dec0] = wcs.crval;
+    Some(Array2::from_shape_fn(
+        (image.num_pixels_y, image.num_pixels_x),
+        |(y, x)| {
+            wcs.pixel_to_world(x as f64, y as f64)
+                .is_some_and(|(ra, dec)| angular_distance(ra, dec, ra0, dec0) <= radius)
+        },
+    ))
+}
+
+/// RA and Dec [deg] of the brightest valid pixel of a plane, None without a
+/// WCS or valid pixels
+fn peak_position(
+    image: &Image,
+    stokes: usize,
+    freq: usize,
+    mask: Option<&Array2<bool>>,
+) -> Option<(f64, f64)> {
+    let wcs = image.wcs.as_ref()?;
+    let ((y, x), _) = image
+        .data
+        .slice(s![stokes, freq, .., ..])
+        .indexed_iter()
+        .filter(|&(index, v)| v.is_finite() && mask.is_none_or(|m| m[index]))
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+    wcs.pixel_to_world(x as f64, y as f64)
+}
+
+/// RMS of every valid pixel, NaN if there are none
+fn calc_rms(valid: &[f64]) -> f64 {
+    let num_pixels = valid.len();
+    let sum_of_sq: f64 = if num_pixels < 1e6 as usize {
+        valid.iter().map(|&x| x * x).sum()
     } else {
-        let sum_of_sq: f64 = data.par_iter().map(|&x| x * x).sum();
-        let mean_sum = sum_of_sq / num_pixels as f64;
-        result = mean_sum.sqrt();
+        valid.par_iter().map(|&x| x * x).sum()
+    };
+
+    (sum_of_sq / num_pixels as f64).sqrt()
+}
+
+/// Standard deviation of the pixels left after iteratively rejecting those
+/// more than `CLIP_SIGMA` standard deviations from the median
+fn calc_sigma_clip(valid: &[f64]) -> f64 {
+    sigma_clipped_stats(valid).1
+}
+
+/// Median and standard deviation of the pixels left after sigma clipping, as
+/// in `calc_sigma_clip`
+pub(crate) fn sigma_clipped_stats(valid: &[f64]) -> (f64, f64) {
+    let mut values = valid.to_vec();
+
+    let mut centre = f64::NAN;
+    let mut std_dev = f64::NAN;
+    for _ in 0..MAX_CLIP_ITERATIONS {
+        let num = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / num;
+        std_dev = (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / num).sqrt();
+
+        centre = median(&mut values.clone());
+        let num_before = values.len();
+        values.retain(|v| (v - centre).abs() <= CLIP_SIGMA * std_dev);
+        if values.len() == num_before || values.is_empty() {
+            break;
+        }
     }
 
-    Ok(result)
+    (centre, std_dev)
+}
+
+/// Median absolute deviation from the median, scaled to a Gaussian sigma
+pub(crate) fn calc_mad(valid: &[f64]) -> f64 {
+    let mut values = valid.to_vec();
+    let centre = median(&mut values);
+    let mut deviations: Vec<f64> = values.iter().map(|v| (v - centre).abs()).collect();
+    MAD_TO_SIGMA * median(&mut deviations)
+}
+
+/// RMS of the negative pixels, NaN if there are none
+fn calc_negative_rms(valid: &[f64]) -> f64 {
+    let (sum_sq, num) = valid
+        .iter()
+        .filter(|&&v| v < 0.0)
+        .fold((0.0, 0), |(s, n), v| (s + v * v, n + 1));
+    (sum_sq / num as f64).sqrt()
+}
+
+/// Median of `values`, which are reordered. NaN if there are none.
+pub(crate) fn median(values: &mut [f64]) -> f64 {
+    if values.is_empty() {
+        return f64::NAN;
+    }
+    let num = values.len();
+    let (below, &mut upper, _) = values.select_nth_unstable_by(num / 2, f64::total_cmp);
+    if num % 2 == 1 {
+        upper
+    } else {
+        let lower = below.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        (lower + upper) / 2.0
+    }
 }
 
 fn calc_dr(max: f64, rms: f64) -> f64 {
     max / rms
 }
 
-fn calc_max(data: &Array2<f64>) -> Result<f64, Box<dyn Error>> {
-    Ok(*data.max()?)
+/// Peak of the valid pixels, NaN if there are none
+fn calc_max(valid: &[f64]) -> f64 {
+    valid.iter().copied().fold(f64::NAN, f64::max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fitsio::FitsFile;
+    use fitsio::images::{ImageDescription, ImageType};
+
+    /// Write `data` as a 2D image with an OBSID to a temporary file
+    fn write_plane(name: &str, data: &Array2<f64>) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("calmet_image_{}_{}.fits", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let description = ImageDescription {
+            data_type: ImageType::Double,
+            dimensions: data.shape(),
+        };
+        let mut fptr = FitsFile::create(&path)
+            .with_custom_primary(&description)
+            .open()
+            .unwrap();
+        let hdu = fptr.primary_hdu().unwrap();
+        hdu.write_image(&mut fptr, data.as_slice().unwrap())
+            .unwrap();
+        hdu.write_key(&mut fptr, "OBSID", 1_090_008_640_i64)
+            .unwrap();
+        path
+    }
+
+    /// Deterministic Gaussian sample with zero mean and standard deviation
+    /// `sigma`, from a linear congruential generator and the Box-Muller
+    /// transform
+    fn gaussian_sample(num: usize, sigma: f64) -> Vec<f64> {
+        let mut state: u64 = 12345;
+        let mut uniform = || {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            ((state >> 11) as f64 + 0.5) / (1u64 << 53) as f64
+        };
+        (0..num)
+            .map(|_| {
+                let (u1, u2) = (uniform(), uniform());
+                sigma * (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn estimators_of_gaussian_noise() {
+        let noise = gaussian_sample(20_000, 2.0);
+        for estimator in [
+            NoiseEstimator::Rms,
+            NoiseEstimator::SigmaClip,
+            NoiseEstimator::Mad,
+            NoiseEstimator::Negative,
+        ] {
+            let estimate = estimator.estimate(&noise);
+            assert!((estimate - 2.0).abs() < 0.1, "{:?} {}", estimator, estimate);
+        }
+    }
+
+    #[test]
+    fn robust_estimators_ignore_sources() {
+        // 1% of pixels are bright sources, which inflate the RMS
+        let mut pixels = gaussian_sample(20_000, 2.0);
+        pixels.iter_mut().step_by(100).for_each(|p| *p += 100.0);
+
+        assert!(NoiseEstimator::Rms.estimate(&pixels) > 9.0);
+        for estimator in [
+            NoiseEstimator::SigmaClip,
+            NoiseEstimator::Mad,
+            NoiseEstimator::Negative,
+        ] {
+            let estimate = estimator.estimate(&pixels);
+            assert!((estimate - 2.0).abs() < 0.1, "{:?} {}", estimator, estimate);
+        }
+    }
+
+    #[test]
+    fn estimators_of_small_samples() {
+        assert_eq!(calc_mad(&[1.0, 2.0, 3.0, 4.0, 100.0]), MAD_TO_SIGMA);
+        assert!(calc_negative_rms(&[1.0, 2.0]).is_nan());
+        assert_eq!(calc_negative_rms(&[-3.0, 5.0, -4.0]), 12.5f64.sqrt());
+        assert_eq!(sigma_clipped_stats(&[5.0; 10]), (5.0, 0.0));
+    }
+
+    #[test]
+    fn dynamic_range_from_residual_noise() {
+        let mut image = Array2::from_elem((4, 4), 0.5);
+        image[[1, 2]] = 10.0;
+        image[[3, 3]] = f64::NAN;
+        let mut residual =
+            Array2::from_shape_fn((4, 4), |(y, x)| if (y + x) % 2 == 0 { 1.0 } else { -1.0 });
+        residual[[0, 0]] = f64::NAN;
+        residual[[0, 1]] = f64::NAN;
+        let model = Array2::from_elem((4, 4), 0.25);
+
+        let paths = [
+            write_plane("dr_image", &image),
+            write_plane("dr_residual", &residual),
+            write_plane("dr_model", &model),
+        ];
+        let set = ImageSet {
+            residual: Some(paths[1].clone()),
+            model: Some(paths[2].clone()),
+        };
+        let result = run_image_calc(
+            &paths[0],
+            &set,
+            &Regex::new(r"(\d{10})").unwrap(),
+            &[NoiseEstimator::Rms],
+            None,
+            &PixelSelection::default(),
+        );
+        paths
+            .iter()
+            .for_each(|path| std::fs::remove_file(path).unwrap());
+        let (obsid, planes) = result.unwrap();
+
+        assert_eq!(obsid, 1_090_008_640);
+        assert_eq!(planes.len(), 1);
+        let plane = &planes[0];
+
+        // The peak comes from the image and the noise from the residual, each
+        // from its own valid pixels
+        assert_eq!(plane.num_valid_pixels, 15);
+        assert_eq!(plane.num_noise_pixels, 14);
+        assert!(plane.noise_from_residual);
+        assert_eq!(plane.peak, 10.0);
+        assert_eq!(plane.noise, vec![1.0]);
+        assert_eq!(plane.dr, vec![10.0]);
+        assert_eq!(plane.residual_peak, 1.0);
+        assert_eq!(plane.model_flux, 4.0);
+    }
 }
@@ -0,0 +1,298 @@
+use crate::io::read::solutions::{CalSolFile, Solutions};
+use crate::metrics::gain_phase::unwrap_phases;
+use ndarray::prelude::*;
+use ndarray_stats::QuantileExt;
+use ndarray_stats::interpolate::Linear;
+use noisy_float::types::n64;
+use std::error::Error;
+use std::path::Path;
+
+/// A step in a tile's gain at a coarse channel boundary
+#[derive(Debug)]
+pub(crate) struct Jump {
+    pub(crate) tile: usize,
+
+    // XX or YY
+    pub(crate) pol: &'static str,
+
+    // First channel after the boundary
+    pub(crate) chan: usize,
+
+    // Frequency of the boundary [Hz], NaN if the solutions have no frequencies
+    pub(crate) freq: f64,
+
+    // Size of the step, in radians for phase or relative gain for amplitude
+    pub(crate) step: f64,
+
+    // Step divided by its standard error
+    pub(crate) significance: f64,
+}
+
+/// Obsid with the phase and amplitude jumps of all tiles, and whether each
+/// tile is fully flagged
+type JumpResults = (usize, Vec<Jump>, Vec<Jump>, Vec<bool>);
+
+/// Find steps in the unwrapped phase and normalised amplitude of each tile at
+/// coarse channel boundaries with a significance above `threshold`
+pub(crate) fn run_jump_calc(
+    file_path: &Path,
+    threshold: f64,
+) -> Result<JumpResults, Box<dyn Error>> {
+    let file = CalSolFile {
+        file_path: file_path.to_path_buf(),
+    };
+    let solutions = file.read_fits()?;
+    let coarse_chans = solutions.coarse_chans();
+
+    let mut phase_jumps = vec![];
+    let mut amp_jumps = vec![];
+
+    for (pol_index, pol) in [(0, "XX"), (3, "YY")] {
+        let gains = solutions.complex_gains.slice(s![.., .., pol_index]);
+
+        // Normalise by the median bandpass to remove structure shared by all
+        // tiles, e.g. the PFB ripple within each coarse channel
+        let amps = gains.map(|c| c.norm());
+        let median_amps = amps
+            .clone()
+            .quantile_axis_skipnan_mut(Axis(0), n64(0.5), &Linear)?;
+
+        for (tile, tile_gains) in gains.axis_iter(Axis(0)).enumerate() {
+            let mut phases = tile_gains.map(|c| c.arg());
+            unwrap_phases(&mut phases);
+            let norm_amps = tile_gains.map(|c| c.norm()) / &median_amps;
+
+            let tile_pol = (tile, pol);
+            phase_jumps.extend(find_jumps(
+                &phases,
+                &coarse_chans,
+                &solutions,
+                tile_pol,
+                threshold,
+            ));
+            amp_jumps.extend(find_jumps(
+                &norm_amps,
+                &coarse_chans,
+                &solutions,
+                tile_pol,
+                threshold,
+            ));
+        }
+    }
+
+    phase_jumps.sort_by_key(|j| (j.tile, j.pol, j.chan));
+    amp_jumps.sort_by_key(|j| (j.tile, j.pol, j.chan));
+
+    // As for flag occupancy, a tile is fully flagged if every chanblock has
+    // a NaN polarisation
+    let flagged_tiles = solutions
+        .complex_gains
+        .outer_iter()
+        .map(|tile| {
+            tile.outer_iter()
+                .all(|pols| pols.iter().any(|c| c.is_nan()))
+        })
+        .collect();
+
+    Ok((solutions.id, phase_jumps, amp_jumps, flagged_tiles))
+}
+
+/// Number of jumps of each tile, over both polarisations. NaN for fully
+/// flagged tiles, which can't have jumps.
+pub(crate) fn count_jumps(jumps: &[Jump], flagged_tiles: &[bool]) -> Vec<f64> {
+    let mut counts: Vec<f64> = flagged_tiles
+        .iter()
+        .map(|&flagged| if flagged { f64::NAN } else { 0.0 })
+        .collect();
+    for jump in jumps {
+        counts[jump.tile] += 1.0;
+    }
+    counts
+}
+
+/// Test each boundary between neighbouring coarse channels for a step.
+///
+/// The two coarse channels are fit with lines of common slope but separate
+/// intercepts; the step is the difference between the intercepts.
+fn find_jumps(
+    values: &Array1<f64>,
+    coarse_chans: &[usize],
+    solutions: &Solutions,
+    (tile, pol): (usize, &'static str),
+    threshold: f64,
+) -> Vec<Jump> {
+    let num_coarse_chans = coarse_chans.iter().max().map_or(0, |c| c + 1);
+    let chans = |coarse: usize| -> Vec<usize> {
+        (0..values.len())
+            .filter(|&c| coarse_chans[c] == coarse && !values[c].is_nan())
+            .collect()
+    };
+
+    (1..num_coarse_chans)
+        .filter_map(|coarse| {
+            let left = chans(coarse - 1);
+            let right = chans(coarse);
+            if left.len() < 2 || right.len() < 2 {
+                return None;
+            }
+
+            let (step, std_err) = fit_step(values, &left, &right)?;
+            // A series without noise, e.g. one that's constant, has no
+            // meaningful significance
+            let significance = step.abs() / std_err;
+            if !significance.is_finite() || significance < threshold {
+                return None;
+            }
+
+            let chan = right[0];
+            let freq = solutions
+                .chan_freqs
+                .as_ref()
+                .map_or(f64::NAN, |f| (f[left[left.len() - 1]] + f[chan]) / 2.0);
+
+            Some(Jump {
+                tile,
+                pol,
+                chan,
+                freq,
+                step,
+                significance,
+            })
+        })
+        .collect()
+}
+
+/// Fit a common slope with separate intercepts to two groups of channels,
+/// returning the step between the groups and its standard error
+fn fit_step(values: &Array1<f64>, left: &[usize], right: &[usize]) -> Option<(f64, f64)> {
+    let mean = |chans: &[usize]| -> (f64, f64) {
+        let n = chans.len() as f64;
+        let x = chans.iter().map(|&c| c as f64).sum::<f64>() / n;
+        let y = chans.iter().map(|&c| values[c]).sum::<f64>() / n;
+        (x, y)
+    };
+    let (x_left, y_left) = mean(left);
+    let (x_right, y_right) = mean(right);
+
+    let groups = [(left, x_left, y_left), (right, x_right, y_right)];
+    let (sxx, sxy) = groups
+        .iter()
+        .flat_map(|(chans, x_mean, y_mean)| {
+            chans
+                .iter()
+                .map(move |&c| (c as f64 - x_mean, values[c] - y_mean))
+        })
+        .fold((0.0, 0.0), |(sxx, sxy), (dx, dy)| {
+            (sxx + dx * dx, sxy + dx * dy)
+        });
+    if sxx == 0.0 {
+        return None;
+    }
+    let slope = sxy / sxx;
+
+    // Difference between the two lines at any channel
+    let step = (y_right - slope * x_right) - (y_left - slope * x_left);
+
+    let ssr: f64 = groups
+        .iter()
+        .flat_map(|(chans, x_mean, y_mean)| {
+            chans
+                .iter()
+                .map(move |&c| values[c] - y_mean - slope * (c as f64 - x_mean))
+        })
+        .map(|r| r * r)
+        .sum();
+    let num_points = left.len() + right.len();
+    if num_points <= 3 {
+        return None;
+    }
+    let variance = ssr / (num_points - 3) as f64;
+    let std_err = (variance
+        * (1.0 / left.len() as f64 + 1.0 / right.len() as f64 + (x_right - x_left).powi(2) / sxx))
+        .sqrt();
+
+    Some((step, std_err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::write::write_solutions;
+    use num_complex::Complex64;
+
+    fn solutions(num_chans: usize) -> Solutions {
+        Solutions {
+            complex_gains: Array3::from_elem((1, num_chans, 4), Complex64::new(1.0, 0.0)),
+            id: 0,
+            num_tiles: 1,
+            num_chans,
+            chan_freqs: None,
+            tile_names: None,
+        }
+    }
+
+    #[test]
+    fn flat_series_has_no_jumps() {
+        let values = Array1::from_elem(32, 1.0);
+        let coarse_chans: Vec<usize> = (0..32).map(|c| c / 16).collect();
+        let jumps = find_jumps(&values, &coarse_chans, &solutions(32), (0, "XX"), 3.0);
+        assert!(jumps.is_empty());
+    }
+
+    #[test]
+    fn finds_real_step() {
+        // Small deterministic scatter on a slope, with a step of 0.5 at the
+        // start of the second coarse channel
+        let values = Array1::from_shape_fn(32, |c| {
+            let step = if c >= 16 { 0.5 } else { 0.0 };
+            0.01 * c as f64 + 0.01 * (c as f64 * 1.7).sin() + step
+        });
+        let coarse_chans: Vec<usize> = (0..32).map(|c| c / 16).collect();
+        let jumps = find_jumps(&values, &coarse_chans, &solutions(32), (0, "XX"), 3.0);
+
+        assert_eq!(jumps.len(), 1);
+        assert_eq!(jumps[0].chan, 16);
+        assert!((jumps[0].step - 0.5).abs() < 0.02);
+        assert!(jumps[0].freq.is_nan());
+    }
+
+    #[test]
+    fn counts_jumps_per_tile() {
+        let jump = |tile| Jump {
+            tile,
+            pol: "XX",
+            chan: 16,
+            freq: f64::NAN,
+            step: 0.5,
+            significance: 10.0,
+        };
+        let counts = count_jumps(&[jump(0), jump(2), jump(2)], &[false, true, false]);
+        assert_eq!(counts[0], 1.0);
+        assert!(counts[1].is_nan());
+        assert_eq!(counts[2], 2.0);
+    }
+
+    #[test]
+    fn flagged_tiles_have_no_jump_count() {
+        let mut solutions = solutions(32);
+        solutions.complex_gains = Array3::from_elem((2, 32, 4), Complex64::new(1.0, 0.0));
+        solutions.num_tiles = 2;
+        solutions
+            .complex_gains
+            .slice_mut(s![1, .., 0])
+            .fill(Complex64::new(f64::NAN, f64::NAN));
+        solutions.tile_names = Some(vec!["Tile011".to_string(), "Tile012".to_string()]);
+
+        let path =
+            std::env::temp_dir().join(format!("calmet_jumps_flagged_{}.fits", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        write_solutions(&path, &solutions, &[]).unwrap();
+        let (_, phase_jumps, _, flagged_tiles) = run_jump_calc(&path, 3.0).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(flagged_tiles, vec![false, true]);
+        let counts = count_jumps(&phase_jumps, &flagged_tiles);
+        assert_eq!(counts[0], 0.0);
+        assert!(counts[1].is_nan());
+    }
+}
@@ -1,4 +1,19 @@
+pub mod background;
+pub mod bandpass;
+pub mod cluster;
+pub mod crossmatch;
+pub mod diff;
+pub mod flags;
 pub mod gain_amplitude;
 pub mod gain_phase;
+pub mod grouping;
+pub mod history;
 pub mod image;
 mod interp;
+pub mod jumps;
+pub mod pca;
+pub mod region;
+pub mod smooth;
+pub mod sources;
+pub mod stability;
+pub mod summary;
@@ -0,0 +1,198 @@
+use crate::io::read::solutions::CalSolFile;
+use crate::metrics::bandpass::normalised_bandpasses;
+use crate::metrics::interp::InterpolateNans;
+use ndarray::prelude::*;
+use std::error::Error;
+use std::path::PathBuf;
+
+/// Maximum number of power iterations per mode
+const MAX_ITERATIONS: usize = 1000;
+
+/// Leading principal components of a stack of bandpasses
+#[derive(Debug)]
+pub(crate) struct Pca {
+    // Eigen-bandpasses, indexed [mode, chan]
+    pub(crate) modes: Array2<f64>,
+
+    // Fraction of the total variance explained by each mode
+    pub(crate) explained_variance: Vec<f64>,
+
+    // Projection of each bandpass onto the modes, indexed [bandpass, mode]
+    pub(crate) loadings: Array2<f64>,
+}
+
+/// Which observation, tile and polarisation each stacked bandpass came from
+#[derive(Debug)]
+pub(crate) struct BandpassLabel {
+    pub(crate) obsid: usize,
+    pub(crate) tile: usize,
+    pub(crate) pol: &'static str,
+}
+
+/// PCA of the normalised amplitude and phase bandpasses of every unflagged
+/// tile and polarisation in the given solutions. All files must have the
+/// same number of channels as the first; others are skipped.
+pub(crate) fn run_pca_calc(
+    paths: &[PathBuf],
+    num_modes: usize,
+) -> Result<(Vec<BandpassLabel>, Pca, Pca), Box<dyn Error>> {
+    let mut labels = vec![];
+    let mut amp_rows: Vec<Array1<f64>> = vec![];
+    let mut phase_rows: Vec<Array1<f64>> = vec![];
+
+    for path in paths {
+        let file = CalSolFile {
+            file_path: path.to_path_buf(),
+        };
+        let Ok(solutions) = file.read_fits() else {
+            eprintln!("Warning: unable to read {}", path.display());
+            continue;
+        };
+
+        if let Some(first) = amp_rows.first()
+            && first.len() != solutions.num_chans
+        {
+            eprintln!(
+                "Warning: obsid {} has a different number of channels, skipping",
+                solutions.id
+            );
+            continue;
+        }
+
+        let bandpasses = normalised_bandpasses(&solutions)?;
+        for (pol, pol_name) in ["XX", "YY"].into_iter().enumerate() {
+            for tile in 0..solutions.num_tiles {
+                let mut amps = bandpasses.amps.slice(s![pol, tile, ..]).to_owned();
+                let mut phases = bandpasses.phases.slice(s![pol, tile, ..]).to_owned();
+
+                // Flagged tiles are all NaN
+                if amps.iter().all(|a| a.is_nan()) || phases.iter().all(|p| p.is_nan()) {
+                    continue;
+                }
+                amps.interp_nans_inplace();
+                phases.interp_nans_inplace();
+
+                labels.push(BandpassLabel {
+                    obsid: solutions.id,
+                    tile,
+                    pol: pol_name,
+                });
+                amp_rows.push(amps);
+                phase_rows.push(phases);
+            }
+        }
+    }
+
+    if labels.len() < 2 {
+        return Err("Need at least two unflagged bandpasses for a PCA".into());
+    }
+
+    let stack = |rows: &[Array1<f64>]| -> Result<Array2<f64>, Box<dyn Error>> {
+        let views: Vec<_> = rows.iter().map(|r| r.view()).collect();
+        Ok(ndarray::stack(Axis(0), &views)?)
+    };
+
+    let amp_pca = pca(stack(&amp_rows)?, num_modes);
+    let phase_pca = pca(stack(&phase_rows)?, num_modes);
+    Ok((labels, amp_pca, phase_pca))
+}
+
+/// PCA of `data`, indexed [sample, feature], keeping `num_modes` components.
+/// Eigenvectors of the covariance matrix are found by power iteration with
+/// deflation.
+fn pca(mut data: Array2<f64>, num_modes: usize) -> Pca {
+    let num_samples = data.nrows() as f64;
+    let mean = data.mean_axis(Axis(0)).expect("No samples for PCA");
+    data -= &mean;
+
+    let mut covariance = data.t().dot(&data) / (num_samples - 1.0);
+    let total_variance = covariance.diag().sum();
+    let num_modes = num_modes.min(covariance.nrows());
+
+    let mut modes = Array2::<f64>::zeros((num_modes, covariance.nrows()));
+    let mut explained_variance = vec![];
+
+    for mut mode in modes.rows_mut() {
+        // Bandpasses with a fit removed are orthogonal to simple vectors like
+        // all ones, so start from a deterministic but irregular one
+        let mut vector = Array1::from_shape_fn(covariance.nrows(), |i| {
+            (i as f64 * 0.618_033_988_7).fract() - 0.5
+        });
+        vector /= vector.dot(&vector).sqrt();
+        let mut eigenvalue = 0.0;
+
+        for _ in 0..MAX_ITERATIONS {
+            let next = covariance.dot(&vector);
+            let norm = next.dot(&next).sqrt();
+            if norm == 0.0 {
+                break;
+            }
+            let next = next / norm;
+            let converged = (&next - &vector).iter().all(|d| d.abs() < 1e-10);
+            vector = next;
+            eigenvalue = norm;
+            if converged {
+                break;
+            }
+        }
+
+        // Remove this component before finding the next
+        let outer = vector
+            .view()
+            .insert_axis(Axis(1))
+            .dot(&vector.view().insert_axis(Axis(0)));
+        covariance.scaled_add(-eigenvalue, &outer);
+
+        explained_variance.push(eigenvalue / total_variance);
+        mode.assign(&vector);
+    }
+
+    let loadings = data.dot(&modes.t());
+
+    Pca {
+        modes,
+        explained_variance,
+        loadings,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn recovers_dominant_mode() {
+        // A shared bandpass plus a strong sine and a weak cosine mode with
+        // different amplitudes in each bandpass
+        let num_chans = 32;
+        let unit = |f: &dyn Fn(f64) -> f64| {
+            let v = Array1::from_shape_fn(num_chans, |c| f(c as f64));
+            &v / v.dot(&v).sqrt()
+        };
+        let sine = unit(&|c| (2.0 * PI * c / num_chans as f64).sin());
+        let cosine = unit(&|c| (4.0 * PI * c / num_chans as f64).cos());
+        let shared = Array1::from_shape_fn(num_chans, |c| 1.0 + 0.01 * c as f64);
+
+        let strong: Vec<f64> = (0..40).map(|i| i as f64 - 19.5).collect();
+        // Uncorrelated with the strong amplitudes
+        let weak: Vec<f64> = (0..40)
+            .map(|i| if i % 4 == 0 || i % 4 == 3 { 0.5 } else { -0.5 })
+            .collect();
+        let data = Array2::from_shape_fn((40, num_chans), |(i, c)| {
+            shared[c] + strong[i] * sine[c] + weak[i] * cosine[c]
+        });
+
+        let result = pca(data, 2);
+
+        // Modes are only defined up to their sign
+        let sign = result.modes.row(0).dot(&sine).signum();
+        assert!((result.modes.row(0).dot(&sine).abs() - 1.0).abs() < 1e-9);
+        assert!((result.modes.row(1).dot(&cosine).abs() - 1.0).abs() < 1e-6);
+        assert!(result.explained_variance[0] > 0.99);
+        assert!((result.explained_variance.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        for (i, &amplitude) in strong.iter().enumerate() {
+            assert!((sign * result.loadings[[i, 0]] - amplitude).abs() < 1e-6);
+        }
+    }
+}
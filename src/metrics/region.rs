@@ -0,0 +1,210 @@
+use crate::io::read::image::Image;
+use crate::io::read::region::{Region, RegionSystem, Shape};
+use crate::io::read::wcs::{Wcs, angular_distance};
+use ndarray::prelude::*;
+use std::error::Error;
+
+/// Pixels that image metrics are restricted to, from DS9 regions and a FITS
+/// mask. Without either, every pixel is used.
+#[derive(Debug, Default)]
+pub(crate) struct PixelSelection {
+    pub(crate) regions: Vec<Region>,
+
+    // Indexed [y, x], true where pixels are used
+    pub(crate) mask: Option<Array2<bool>>,
+}
+
+impl PixelSelection {
+    /// Mask of the pixels of `image` to use, None if there's nothing to
+    /// restrict. Pixels have to be inside an included region, or any region
+    /// if all of them are excluded, outside every excluded region and on in
+    /// the mask.
+    pub(crate) fn pixel_mask(&self, image: &Image) -> Result<Option<Array2<bool>>, Box<dyn Error>> {
+        if self.regions.is_empty() && self.mask.is_none() {
+            return Ok(None);
+        }
+
+        let shape = (image.num_pixels_y, image.num_pixels_x);
+        let mut selected = match &self.mask {
+            Some(mask) if mask.dim() != shape => {
+                return Err(format!(
+                    "mask has {:?} pixels but the image has {:?}",
+                    mask.dim(),
+                    shape
+                )
+                .into());
+            }
+            Some(mask) => mask.clone(),
+            None => Array2::from_elem(shape, true),
+        };
+
+        if self.regions.iter().any(|r| !r.exclude) {
+            let mut included = Array2::from_elem(shape, false);
+            for region in self.regions.iter().filter(|r| !r.exclude) {
+                included.zip_mut_with(&region_mask(region, image)?, |i, &r| *i |= r);
+            }
+            selected.zip_mut_with(&included, |s, &i| *s &= i);
+        }
+        for region in self.regions.iter().filter(|r| r.exclude) {
+            selected.zip_mut_with(&region_mask(region, image)?, |s, &r| *s &= !r);
+        }
+
+        Ok(Some(selected))
+    }
+}
+
+/// Pixels of `image` inside `region`
+fn region_mask(region: &Region, image: &Image) -> Result<Array2<bool>, Box<dyn Error>> {
+    let shape = (image.num_pixels_y, image.num_pixels_x);
+    let wcs = || {
+        image
+            .wcs
+            .as_ref()
+            .ok_or("sky coordinate regions need the image to have a celestial WCS")
+    };
+
+    // Sky circles are tested on the sphere, other sky shapes are converted to
+    // pixel polygons
+    let pixel_shape = match (&region.shape, region.system) {
+        (shape, RegionSystem::Image) => shape.clone(),
+        (&Shape::Circle { centre, radius }, RegionSystem::Sky) => {
+            let wcs = wcs()?;
+            return Ok(Array2::from_shape_fn(shape, |(y, x)| {
+                wcs.pixel_to_world(x as f64, y as f64)
+                    .is_some_and(|(ra, dec)| {
+                        angular_distance(ra, dec, centre.0, centre.1) <= radius
+                    })
+            }));
+        }
+        (
+            &Shape::Box {
+                centre,
+                width,
+                height,
+                angle,
+            },
+            RegionSystem::Sky,
+        ) => Shape::Polygon(sky_to_pixels(
+            wcs()?,
+            &box_corners(centre, width, height, angle),
+        )?),
+        (Shape::Polygon(vertices), RegionSystem::Sky) => {
+            Shape::Polygon(sky_to_pixels(wcs()?, vertices)?)
+        }
+    };
+
+    Ok(Array2::from_shape_fn(shape, |(y, x)| {
+        contains(&pixel_shape, x as f64, y as f64)
+    }))
+}
+
+/// Corners of a box on the sky, offsetting from its centre in the tangent
+/// plane, which is accurate for boxes much smaller than a radian
+fn box_corners(centre: (f64, f64), width: f64, height: f64, angle: f64) -> Vec<(f64, f64)> {
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let cos_dec = centre.1.to_radians().cos();
+    [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+        .iter()
+        .map(|(sx, sy)| {
+            let (along, up) = (sx * width / 2.0, sy * height / 2.0);
+            let west = along * cos - up * sin;
+            let north = along * sin + up * cos;
+            (centre.0 - west / cos_dec, centre.1 + north)
+        })
+        .collect()
+}
+
+fn sky_to_pixels(wcs: &Wcs, vertices: &[(f64, f64)]) -> Result<Vec<(f64, f64)>, Box<dyn Error>> {
+    vertices
+        .iter()
+        .map(|&(ra, dec)| {
+            wcs.world_to_pixel(ra, dec)
+                .ok_or_else(|| format!("region vertex at {} {} is off the image", ra, dec).into())
+        })
+        .collect()
+}
+
+/// Whether the pixel (x, y) is inside a shape in pixel coordinates
+fn contains(shape: &Shape, x: f64, y: f64) -> bool {
+    match shape {
+        Shape::Circle { centre, radius } => (x - centre.0).hypot(y - centre.1) <= *radius,
+        Shape::Box {
+            centre,
+            width,
+            height,
+            angle,
+        } => {
+            let (sin, cos) = angle.to_radians().sin_cos();
+            let (dx, dy) = (x - centre.0, y - centre.1);
+            let along = dx * cos + dy * sin;
+            let up = -dx * sin + dy * cos;
+            along.abs() <= width / 2.0 && up.abs() <= height / 2.0
+        }
+        // Even-odd rule, counting edge crossings of a ray along +x
+        Shape::Polygon(vertices) => {
+            let mut inside = false;
+            let mut previous = vertices[vertices.len() - 1];
+            for &vertex in vertices {
+                if (vertex.1 > y) != (previous.1 > y) {
+                    let crossing = vertex.0
+                        + (y - vertex.1) * (previous.0 - vertex.0) / (previous.1 - vertex.1);
+                    if x < crossing {
+                        inside = !inside;
+                    }
+                }
+                previous = vertex;
+            }
+            inside
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn circle_includes_its_edge() {
+        let circle = Shape::Circle {
+            centre: (10.0, 10.0),
+            radius: 3.0,
+        };
+        assert!(contains(&circle, 10.0, 10.0));
+        assert!(contains(&circle, 13.0, 10.0));
+        assert!(!contains(&circle, 12.5, 12.5));
+    }
+
+    #[test]
+    fn rotated_box() {
+        let rotated = Shape::Box {
+            centre: (0.0, 0.0),
+            width: 10.0,
+            height: 2.0,
+            angle: 90.0,
+        };
+        // Rotated by 90 degrees, the long side is along y
+        assert!(contains(&rotated, 0.0, 4.5));
+        assert!(!contains(&rotated, 4.5, 0.0));
+        assert!(contains(&rotated, 0.9, -4.9));
+    }
+
+    #[test]
+    fn concave_polygon() {
+        // A U shape open at the top
+        let polygon = Shape::Polygon(vec![
+            (0.0, 0.0),
+            (6.0, 0.0),
+            (6.0, 6.0),
+            (4.0, 6.0),
+            (4.0, 2.0),
+            (2.0, 2.0),
+            (2.0, 6.0),
+            (0.0, 6.0),
+        ]);
+        assert!(contains(&polygon, 1.0, 5.0));
+        assert!(contains(&polygon, 3.0, 1.0));
+        assert!(contains(&polygon, 5.0, 5.0));
+        assert!(!contains(&polygon, 3.0, 4.0));
+        assert!(!contains(&polygon, 7.0, 1.0));
+    }
+}
@@ -0,0 +1,261 @@
+use crate::io::read::solutions::{CalSolFile, Solutions};
+use crate::metrics::gain_phase::{LinearRegression, unwrap_phases};
+use ndarray::prelude::*;
+use num_complex::Complex64;
+use std::error::Error;
+use std::path::Path;
+
+/// Smoothed solutions of every timeblock of a file, as in `smooth_solutions`
+pub(crate) fn run_smooth_calc(
+    file_path: &Path,
+    amp_order: usize,
+) -> Result<Vec<Solutions>, Box<dyn Error>> {
+    let file = CalSolFile {
+        file_path: file_path.to_path_buf(),
+    };
+    let mut timeblocks = file.read_timeblocks()?;
+    for solutions in &mut timeblocks {
+        smooth_solutions(solutions, amp_order);
+    }
+    Ok(timeblocks)
+}
+
+/// Replace each tile's XX and YY gains with a polynomial fit to the amplitude
+/// and a delay plus offset fit to the phase, filling in flagged channels.
+/// Fully flagged tiles are left as NaN and XY/YX are kept, with flagged
+/// channels set to zero.
+fn smooth_solutions(solutions: &mut Solutions, amp_order: usize) {
+    // Fit against frequency if known, so that the phase slope is a delay
+    let x = solutions
+        .chan_freqs
+        .clone()
+        .unwrap_or_else(|| Array1::range(0.0, solutions.num_chans as f64, 1.0));
+
+    for mut tile_gains in solutions.complex_gains.axis_iter_mut(Axis(0)) {
+        if tile_gains.iter().all(|c| c.is_nan()) {
+            continue;
+        }
+
+        for pol in [0, 3] {
+            let mut gains = tile_gains.slice_mut(s![.., pol]);
+
+            let amps = gains.map(|c| c.norm());
+            let mut phases = gains.map(|c| c.arg());
+            unwrap_phases(&mut phases);
+
+            let smooth_amps = poly_smooth(&x, &amps, amp_order);
+            let smooth_phases = linear_smooth(&x, &phases);
+
+            gains.assign(
+                &ndarray::Zip::from(&smooth_amps)
+                    .and(&smooth_phases)
+                    .map_collect(|&amp, &phase| Complex64::from_polar(amp, phase)),
+            );
+        }
+
+        for pol in [1, 2] {
+            tile_gains
+                .slice_mut(s![.., pol])
+                .mapv_inplace(|c| if c.is_nan() { Complex64::ZERO } else { c });
+        }
+    }
+}
+
+/// Valid (non-NaN) x and y values
+fn valid_points(x: &Array1<f64>, y: &Array1<f64>) -> (Array1<f64>, Array1<f64>) {
+    let (valid_x, valid_y): (Vec<f64>, Vec<f64>) = x
+        .iter()
+        .zip(y)
+        .filter(|(_, y)| !y.is_nan())
+        .map(|(&x, &y)| (x, y))
+        .unzip();
+    (Array1::from(valid_x), Array1::from(valid_y))
+}
+
+/// Straight-line fit to the non-NaN values of y, evaluated at every x
+fn linear_smooth(x: &Array1<f64>, y: &Array1<f64>) -> Array1<f64> {
+    let (valid_x, valid_y) = valid_points(x, y);
+    if valid_x.len() < 2 {
+        return Array1::from_elem(x.len(), f64::NAN);
+    }
+
+    let mut fit = LinearRegression::new(valid_x, valid_y);
+    fit.fit();
+    x * fit.gradient.unwrap() + fit.intercept.unwrap()
+}
+
+/// Least-squares polynomial fit to the non-NaN values of y, evaluated at
+/// every x. The order is reduced if there are too few valid points.
+fn poly_smooth(x: &Array1<f64>, y: &Array1<f64>, order: usize) -> Array1<f64> {
+    let (valid_x, valid_y) = valid_points(x, y);
+    if valid_x.is_empty() {
+        return Array1::from_elem(x.len(), f64::NAN);
+    }
+    let num_coeffs = (order + 1).min(valid_x.len());
+
+    // Scale x to [-1, 1] to keep the normal equations well conditioned
+    let (min, max) = valid_x
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &v| {
+            (lo.min(v), hi.max(v))
+        });
+    let half_range = ((max - min) / 2.0).max(f64::EPSILON);
+    let scale = |v: f64| (v - min) / half_range - 1.0;
+
+    let vandermonde = |v: f64| -> Array1<f64> {
+        let v = scale(v);
+        Array1::from_iter((0..num_coeffs).map(|p| v.powi(p as i32)))
+    };
+
+    let mut normal = Array2::<f64>::zeros((num_coeffs, num_coeffs));
+    let mut rhs = Array1::<f64>::zeros(num_coeffs);
+    for (&xi, &yi) in valid_x.iter().zip(&valid_y) {
+        let row = vandermonde(xi);
+        for i in 0..num_coeffs {
+            rhs[i] += row[i] * yi;
+            for j in 0..num_coeffs {
+                normal[[i, j]] += row[i] * row[j];
+            }
+        }
+    }
+
+    match solve(normal, rhs) {
+        Some(coeffs) => x.mapv(|v| vandermonde(v).dot(&coeffs)),
+        None => Array1::from_elem(x.len(), f64::NAN),
+    }
+}
+
+/// Solve a x = b with Gaussian elimination and partial pivoting
+fn solve(mut a: Array2<f64>, mut b: Array1<f64>) -> Option<Array1<f64>> {
+    let n = b.len();
+
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[[i, col]].abs().total_cmp(&a[[j, col]].abs()))?;
+        if a[[pivot, col]].abs() < f64::EPSILON {
+            return None;
+        }
+        if pivot != col {
+            for k in 0..n {
+                a.swap([col, k], [pivot, k]);
+            }
+            b.swap(col, pivot);
+        }
+
+        for row in col + 1..n {
+            let factor = a[[row, col]] / a[[col, col]];
+            for k in col..n {
+                a[[row, k]] -= factor * a[[col, k]];
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+
+    let mut solution = Array1::<f64>::zeros(n);
+    for row in (0..n).rev() {
+        let sum: f64 = (row + 1..n).map(|k| a[[row, k]] * solution[k]).sum();
+        solution[row] = (b[row] - sum) / a[[row, row]];
+    }
+
+    Some(solution)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::write::write_solutions;
+
+    fn assert_all_close(actual: &Array1<f64>, expected: &Array1<f64>, tolerance: f64) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < tolerance, "{} != {}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn solve_known_system() {
+        let a = array![[2.0, 1.0, -1.0], [-3.0, -1.0, 2.0], [-2.0, 1.0, 2.0]];
+        let b = array![8.0, -11.0, -3.0];
+        assert_all_close(&solve(a, b).unwrap(), &array![2.0, 3.0, -1.0], 1e-12);
+    }
+
+    #[test]
+    fn solve_singular_system() {
+        let a = array![[1.0, 2.0], [2.0, 4.0]];
+        assert!(solve(a, array![1.0, 2.0]).is_none());
+    }
+
+    #[test]
+    fn poly_smooth_recovers_polynomial() {
+        let x = Array1::range(0.0, 32.0, 1.0);
+        let y = x.mapv(|v| 1.0 + 0.1 * v - 0.01 * v * v + 0.0002 * v * v * v);
+        assert_all_close(&poly_smooth(&x, &y, 3), &y, 1e-9);
+    }
+
+    #[test]
+    fn poly_smooth_fills_gap() {
+        let x = Array1::range(0.0, 32.0, 1.0);
+        let y = x.mapv(|v| 2.0 - 0.05 * v + 0.001 * v * v);
+        let mut flagged = y.clone();
+        flagged.slice_mut(s![10..14]).fill(f64::NAN);
+        assert_all_close(&poly_smooth(&x, &flagged, 2), &y, 1e-9);
+    }
+
+    #[test]
+    fn poly_smooth_short_input() {
+        let x = Array1::range(0.0, 4.0, 1.0);
+
+        // Too few points for the order, so the order is reduced
+        let y = array![f64::NAN, 3.0, f64::NAN, f64::NAN];
+        assert_all_close(&poly_smooth(&x, &y, 5), &Array1::from_elem(4, 3.0), 1e-12);
+
+        let y = Array1::from_elem(4, f64::NAN);
+        assert!(poly_smooth(&x, &y, 5).iter().all(|v| v.is_nan()));
+    }
+
+    #[test]
+    fn smooths_file_and_fills_flags() {
+        let (num_tiles, num_chans) = (2, 32);
+        let mut complex_gains = Array3::from_elem(
+            (num_tiles, num_chans, 4),
+            Complex64::new(f64::NAN, f64::NAN),
+        );
+        for chan in 0..num_chans {
+            let c = chan as f64;
+            let xx = Complex64::from_polar(1.0 + 0.01 * c, 0.1 + 0.02 * c);
+            let xy = Complex64::new(0.01, 0.0);
+            complex_gains
+                .slice_mut(s![0, chan, ..])
+                .assign(&array![xx, xy, xy, xx]);
+        }
+        // Flag some channels of the first tile, and all of the second
+        complex_gains
+            .slice_mut(s![0, 5..8, ..])
+            .fill(Complex64::new(f64::NAN, f64::NAN));
+
+        let path = std::env::temp_dir().join(format!("calmet_smooth_{}.fits", std::process::id()));
+        let solutions = Solutions {
+            complex_gains,
+            id: 1_090_008_640,
+            num_tiles,
+            num_chans,
+            chan_freqs: None,
+            tile_names: None,
+        };
+        write_solutions(&path, &solutions, &[]).unwrap();
+        let timeblocks = run_smooth_calc(&path, 1).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(timeblocks.len(), 1);
+        let gains = &timeblocks[0].complex_gains;
+        for chan in 0..num_chans {
+            let c = chan as f64;
+            let expected = Complex64::from_polar(1.0 + 0.01 * c, 0.1 + 0.02 * c);
+            for pol in [0, 3] {
+                assert!((gains[[0, chan, pol]] - expected).norm() < 1e-9);
+            }
+        }
+        // Flagged XY and YX are zeroed, others kept
+        assert_eq!(gains[[0, 6, 1]], Complex64::ZERO);
+        assert_eq!(gains[[0, 4, 2]], Complex64::new(0.01, 0.0));
+        assert!(gains.slice(s![1, .., ..]).iter().all(|c| c.is_nan()));
+    }
+}
@@ -0,0 +1,338 @@
+use crate::io::read::image::{Image, ImageFile};
+use crate::metrics::background::{NoiseMaps, calc_noise_maps};
+use ndarray::Zip;
+use ndarray::prelude::*;
+use regex::Regex;
+use std::error::Error;
+use std::path::Path;
+
+/// Signal to noise above which islands count as sources in `SourceCounts`
+pub(crate) const DETECTION_SNR: f64 = 5.0;
+
+/// Ratio of the FWHM to the standard deviation of a Gaussian
+const FWHM_TO_SIGMA: f64 = 2.354_820_045;
+
+/// Obsid with the sources and island counts of an image
+type SourceResults = (usize, Vec<Source>, Vec<SourceCounts>);
+
+/// A source found in a single Stokes and frequency plane, measured from the
+/// moments of its island
+#[derive(Debug, Clone)]
+pub(crate) struct Source {
+    // FITS Stokes code and frequency [Hz] of the plane, NaN if the image has
+    // no such axis
+    pub(crate) stokes: f64,
+    pub(crate) freq: f64,
+
+    // Index of the island within its plane
+    pub(crate) island: usize,
+
+    // Flux weighted centroid in 0-indexed pixels, and on the sky [deg] if
+    // the image has a WCS
+    pub(crate) x: f64,
+    pub(crate) y: f64,
+    pub(crate) ra: f64,
+    pub(crate) dec: f64,
+
+    // Brightest pixel above the background, and its signal to noise
+    pub(crate) peak_flux: f64,
+    pub(crate) peak_snr: f64,
+
+    // Sum of the island above the background divided by the beam area, NaN
+    // without the beam size or a WCS
+    pub(crate) int_flux: f64,
+
+    // FWHM of the island along its major and minor axes [pixels], and the
+    // major axis angle anticlockwise from the x axis [deg]
+    pub(crate) major: f64,
+    pub(crate) minor: f64,
+    pub(crate) pa: f64,
+
+    // Background and RMS at the peak
+    pub(crate) background: f64,
+    pub(crate) rms: f64,
+
+    pub(crate) num_pixels: usize,
+}
+
+/// Island counts of a single Stokes and frequency plane
+#[derive(Debug)]
+pub(crate) struct SourceCounts {
+    pub(crate) stokes: f64,
+    pub(crate) freq: f64,
+
+    // Islands of positive pixels
+    pub(crate) num_islands: usize,
+
+    // Positive islands peaking above `DETECTION_SNR`
+    pub(crate) num_detections: usize,
+
+    // Islands of negative pixels, found with the same thresholds. Real sky
+    // has none, so these are noise spikes or artefacts.
+    pub(crate) num_negative: usize,
+}
+
+impl SourceCounts {
+    /// Fraction of all islands that are negative, which is near 0 for clean
+    /// images and approaches 0.5 when most islands are artefacts or noise
+    pub(crate) fn negative_fraction(&self) -> f64 {
+        self.num_negative as f64 / (self.num_islands + self.num_negative) as f64
+    }
+}
+
+/// Obsid with the sources and island counts of every plane of an image, using
+/// noise maps made with `grid` and `box_size` as in `calc_noise_maps`
+pub(crate) fn run_source_calc(
+    path: &Path,
+    obsid_pattern: &Regex,
+    grid: usize,
+    box_size: usize,
+    seed: f64,
+    flood: f64,
+) -> Result<SourceResults, Box<dyn Error>> {
+    let image = ImageFile {
+        file_path: path.to_path_buf(),
+        obsid_pattern: obsid_pattern.clone(),
+    }
+    .read_fits()?;
+
+    let maps = calc_noise_maps(&image, grid, box_size);
+    let (sources, counts) = find_sources(&image, &maps, seed, flood);
+    Ok((image.id, sources, counts))
+}
+
+/// Find islands of pixels whose signal to noise is above `flood` and that
+/// contain a pixel above `seed`, in the style of Aegean. Positive islands are
+/// measured as sources, negative ones are only counted.
+pub(crate) fn find_sources(
+    image: &Image,
+    maps: &NoiseMaps,
+    seed: f64,
+    flood: f64,
+) -> (Vec<Source>, Vec<SourceCounts>) {
+    let mut sources = vec![];
+    let mut counts = vec![];
+
+    let (num_stokes, num_freqs, _, _) = image.data.dim();
+    for stokes in 0..num_stokes {
+        for freq in 0..num_freqs {
+            let plane = image.data.slice(s![stokes, freq, .., ..]);
+            let background = maps.background.slice(s![stokes, freq, .., ..]);
+            let rms = maps.rms.slice(s![stokes, freq, .., ..]);
+            let snr = Zip::from(&plane)
+                .and(&background)
+                .and(&rms)
+                .map_collect(|&v, &b, &r| (v - b) / r);
+
+            let islands = find_islands(snr.view(), seed, flood);
+            let negative = find_islands(snr.mapv(|v| -v).view(), seed, flood);
+
+            let plane_sources: Vec<Source> = islands
+                .iter()
+                .enumerate()
+                .map(|(island, pixels)| Source {
+                    stokes: image.stokes[stokes],
+                    freq: image.freqs[freq],
+                    island,
+                    ..measure_island(image, plane, background, rms, pixels)
+                })
+                .collect();
+
+            counts.push(SourceCounts {
+                stokes: image.stokes[stokes],
+                freq: image.freqs[freq],
+                num_islands: islands.len(),
+                num_detections: plane_sources
+                    .iter()
+                    .filter(|s| s.peak_snr >= DETECTION_SNR)
+                    .count(),
+                num_negative: negative.len(),
+            });
+            sources.extend(plane_sources);
+        }
+    }
+
+    (sources, counts)
+}
+
+/// Pixels (y, x) of each island, flood filling 8-connected pixels above
+/// `flood` out from each pixel above `seed`. NaN pixels never join islands.
+fn find_islands(snr: ArrayView2<f64>, seed: f64, flood: f64) -> Vec<Vec<(usize, usize)>> {
+    let (num_y, num_x) = snr.dim();
+    let mut visited = Array2::from_elem((num_y, num_x), false);
+    let mut islands = vec![];
+
+    for ((y, x), &value) in snr.indexed_iter() {
+        if visited[[y, x]] || value.is_nan() || value < seed {
+            continue;
+        }
+
+        let mut island = vec![];
+        let mut stack = vec![(y, x)];
+        visited[[y, x]] = true;
+        while let Some((py, px)) = stack.pop() {
+            island.push((py, px));
+            for ny in py.saturating_sub(1)..(py + 2).min(num_y) {
+                for nx in px.saturating_sub(1)..(px + 2).min(num_x) {
+                    if !visited[[ny, nx]] && snr[[ny, nx]] >= flood {
+                        visited[[ny, nx]] = true;
+                        stack.push((ny, nx));
+                    }
+                }
+            }
+        }
+        islands.push(island);
+    }
+
+    islands
+}
+
+/// Position, flux and shape of a positive island from the moments of its
+/// background subtracted pixels. The plane and island index are left for the
+/// caller to fill in.
+fn measure_island(
+    image: &Image,
+    plane: ArrayView2<f64>,
+    background: ArrayView2<f64>,
+    rms: ArrayView2<f64>,
+    pixels: &[(usize, usize)],
+) -> Source {
+    let flux = |&(y, x): &(usize, usize)| plane[[y, x]] - background[[y, x]];
+
+    let total: f64 = pixels.iter().map(flux).sum();
+    let (sum_x, sum_y) = pixels.iter().fold((0.0, 0.0), |(sx, sy), p| {
+        (sx + flux(p) * p.1 as f64, sy + flux(p) * p.0 as f64)
+    });
+    let (x, y) = (sum_x / total, sum_y / total);
+
+    // Second moments give the axes of an equivalent Gaussian
+    let (mut xx, mut yy, mut xy) = (0.0, 0.0, 0.0);
+    for p in pixels {
+        let (dx, dy) = (p.1 as f64 - x, p.0 as f64 - y);
+        xx += flux(p) * dx * dx;
+        yy += flux(p) * dy * dy;
+        xy += flux(p) * dx * dy;
+    }
+    let (xx, yy, xy) = (xx / total, yy / total, xy / total);
+    let spread = ((xx - yy) / 2.0).hypot(xy);
+    let major = FWHM_TO_SIGMA * ((xx + yy) / 2.0 + spread).max(0.0).sqrt();
+    let minor = FWHM_TO_SIGMA * ((xx + yy) / 2.0 - spread).max(0.0).sqrt();
+    let pa = 0.5 * (2.0 * xy).atan2(xx - yy).to_degrees();
+
+    let peak = *pixels
+        .iter()
+        .max_by(|a, b| flux(a).total_cmp(&flux(b)))
+        .expect("islands have at least one pixel");
+
+    let (ra, dec) = image
+        .wcs
+        .as_ref()
+        .and_then(|wcs| wcs.pixel_to_world(x, y))
+        .unwrap_or((f64::NAN, f64::NAN));
+
+    // Area of a Gaussian beam in pixels
+    let beam_pixels = match (&image.beam, &image.wcs) {
+        (Some([bmaj, bmin, _]), Some(wcs)) => {
+            std::f64::consts::PI / (4.0 * 2f64.ln()) * bmaj * bmin / wcs.pixel_area()
+        }
+        _ => f64::NAN,
+    };
+
+    Source {
+        stokes: f64::NAN,
+        freq: f64::NAN,
+        island: 0,
+        x,
+        y,
+        ra,
+        dec,
+        peak_flux: flux(&peak),
+        peak_snr: flux(&peak) / rms[peak],
+        int_flux: total / beam_pixels,
+        major,
+        minor,
+        pa,
+        background: background[peak],
+        rms: rms[peak],
+        num_pixels: pixels.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Image of elliptical Gaussians (amplitude, x, y, sigma_x, sigma_y) on a
+    /// zero background with unit RMS
+    fn gaussian_image(gaussians: &[(f64, f64, f64, f64, f64)]) -> (Image, NoiseMaps) {
+        let data = Array4::from_shape_fn((1, 1, 80, 80), |(_, _, y, x)| {
+            gaussians
+                .iter()
+                .map(|&(amp, cx, cy, sx, sy)| {
+                    let (dx, dy) = ((x as f64 - cx) / sx, (y as f64 - cy) / sy);
+                    amp * (-0.5 * (dx * dx + dy * dy)).exp()
+                })
+                .sum()
+        });
+        let maps = NoiseMaps {
+            background: Array4::zeros(data.dim()),
+            rms: Array4::ones(data.dim()),
+        };
+        let image = Image {
+            data,
+            id: 0,
+            num_pixels_x: 80,
+            num_pixels_y: 80,
+            stokes: vec![1.0],
+            freqs: vec![f64::NAN],
+            wcs: None,
+            beam: None,
+        };
+        (image, maps)
+    }
+
+    #[test]
+    fn measures_planted_gaussian() {
+        let (image, maps) = gaussian_image(&[(100.0, 30.0, 40.0, 3.0, 1.5)]);
+        let (sources, counts) = find_sources(&image, &maps, 5.0, 1e-3);
+
+        assert_eq!(sources.len(), 1);
+        assert_eq!(counts[0].num_islands, 1);
+        let source = &sources[0];
+        assert!((source.x - 30.0).abs() < 1e-9 && (source.y - 40.0).abs() < 1e-9);
+        assert!((source.major - FWHM_TO_SIGMA * 3.0).abs() < 0.01);
+        assert!((source.minor - FWHM_TO_SIGMA * 1.5).abs() < 0.01);
+        assert!(source.pa.abs() < 1e-6);
+        assert_eq!(source.peak_flux, 100.0);
+        assert_eq!(source.peak_snr, 100.0);
+        assert!(source.int_flux.is_nan() && source.ra.is_nan());
+    }
+
+    #[test]
+    fn counts_detections_and_negative_islands() {
+        let (image, maps) = gaussian_image(&[
+            (100.0, 20.0, 20.0, 2.0, 2.0),
+            (DETECTION_SNR - 1.0, 60.0, 20.0, 1.0, 1.0),
+            (-20.0, 40.0, 60.0, 1.0, 1.0),
+        ]);
+        let (sources, counts) = find_sources(&image, &maps, 3.0, 2.0);
+
+        assert_eq!(sources.len(), 2);
+        assert_eq!(counts[0].num_islands, 2);
+        assert_eq!(counts[0].num_detections, 1);
+        assert_eq!(counts[0].num_negative, 1);
+        assert!((counts[0].negative_fraction() - 1.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn islands_join_diagonals_and_skip_nan() {
+        let snr = array![
+            [6.0, 0.0, 0.0, 0.0],
+            [0.0, 3.0, f64::NAN, 0.0],
+            [0.0, 0.0, 0.0, 6.0],
+        ];
+        let mut islands = find_islands(snr.view(), 5.0, 2.0);
+        islands.iter_mut().for_each(|island| island.sort());
+        assert_eq!(islands, vec![vec![(0, 0), (1, 1)], vec![(2, 3)]]);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::io::read::solutions::{CalSolFile, Solutions};
+use crate::metrics::bandpass::{align_to, nan_rms, normalised_bandpasses, wrap_phase};
+use ndarray::prelude::*;
+use ndarray_stats::QuantileExt;
+use ndarray_stats::interpolate::Linear;
+use noisy_float::types::n64;
+use std::error::Error;
+use std::path::PathBuf;
+
+/// Bandpass variation across a set of observations
+#[derive(Debug)]
+pub(crate) struct Stability {
+    // Obsids of the solutions that were used
+    pub(crate) obsids: Vec<usize>,
+
+    // Per-tile RMS deviation of the normalised amplitude from the night median
+    pub(crate) tile_amp_rms: Vec<f64>,
+
+    // Per-tile RMS deviation of the phase from the night median [rad]
+    pub(crate) tile_phase_rms: Vec<f64>,
+
+    // Per-observation RMS deviation of the normalised amplitude over all tiles
+    pub(crate) obs_amp_distance: Vec<f64>,
+
+    // Per-observation RMS deviation of the phase over all tiles [rad]
+    pub(crate) obs_phase_distance: Vec<f64>,
+}
+
+/// Compare every tile's normalised bandpass against its median over all
+/// observations. Solutions are aligned to the tiles and channels of the first
+/// file with `align_to`, so tiles are numbered as in the first file; files
+/// whose channels don't match are skipped.
+pub(crate) fn run_stability_calc(paths: &[PathBuf]) -> Result<Stability, Box<dyn Error>> {
+    let mut obsids = vec![];
+    let mut amp_views: Vec<Array3<f64>> = vec![];
+    let mut phase_views = vec![];
+    let mut first: Option<Solutions> = None;
+
+    for path in paths {
+        let file = CalSolFile {
+            file_path: path.to_path_buf(),
+        };
+        let Ok(mut solutions) = file.read_fits() else {
+            eprintln!("Warning: unable to read {}", path.display());
+            continue;
+        };
+
+        if let Some(first) = &first {
+            let Some(gains) = align_to(&solutions, first) else {
+                eprintln!(
+                    "Warning: channels of obsid {} don't match obsid {}, skipping",
+                    solutions.id, first.id
+                );
+                continue;
+            };
+            solutions.complex_gains = gains;
+            solutions.num_tiles = first.num_tiles;
+            solutions.num_chans = first.num_chans;
+        }
+
+        let bandpasses = normalised_bandpasses(&solutions)?;
+        obsids.push(solutions.id);
+        amp_views.push(bandpasses.amps);
+        phase_views.push(bandpasses.phases);
+        first.get_or_insert(solutions);
+    }
+
+    if obsids.is_empty() {
+        return Err("No solutions could be read".into());
+    }
+
+    // Stacks are indexed [obs, pol, tile, chan]
+    let amps = ndarray::stack(
+        Axis(0),
+        &amp_views.iter().map(|a| a.view()).collect::<Vec<_>>(),
+    )?;
+    let phases = ndarray::stack(
+        Axis(0),
+        &phase_views.iter().map(|a| a.view()).collect::<Vec<_>>(),
+    )?;
+
+    let median_amps = amps
+        .clone()
+        .quantile_axis_skipnan_mut(Axis(0), n64(0.5), &Linear)?;
+    let median_phases = phases
+        .clone()
+        .quantile_axis_skipnan_mut(Axis(0), n64(0.5), &Linear)?;
+
+    let amp_devs = &amps - &median_amps.insert_axis(Axis(0));
+    let mut phase_devs = &phases - &median_phases.insert_axis(Axis(0));
+    phase_devs.mapv_inplace(wrap_phase);
+
+    // Tiles are along axis 2, observations along axis 0
+    let tile_rms = |devs: &Array4<f64>| -> Vec<f64> {
+        devs.axis_iter(Axis(2))
+            .map(|tile| nan_rms(tile.iter()))
+            .collect()
+    };
+    let obs_rms = |devs: &Array4<f64>| -> Vec<f64> {
+        devs.axis_iter(Axis(0))
+            .map(|obs| nan_rms(obs.iter()))
+            .collect()
+    };
+
+    Ok(Stability {
+        obsids,
+        tile_amp_rms: tile_rms(&amp_devs),
+        tile_phase_rms: tile_rms(&phase_devs),
+        obs_amp_distance: obs_rms(&amp_devs),
+        obs_phase_distance: obs_rms(&phase_devs),
+    })
+}
@@ -0,0 +1,174 @@
+use std::collections::BTreeSet;
+
+/// Obsids with the per-tile values of each observation
+pub(crate) type TileValues = (Vec<usize>, Vec<Vec<f64>>);
+
+/// Obsids with a single value per observation
+pub(crate) type ObsValues = (Vec<usize>, Vec<f64>);
+
+/// Per-tile metrics included in the summary, named after their results files
+pub(crate) const SUMMARY_METRICS: [&str; 10] = [
+    "xx_gain_smoothness",
+    "yy_gain_smoothness",
+    "xx_yy_amp_ratio",
+    "xx_phase_rmse",
+    "yy_phase_rmse",
+    "xx_yy_phase_diff_mean",
+    "xx_yy_phase_diff_rms",
+    "phase_jump_count",
+    "amp_jump_count",
+    "flag_occupancy_tile",
+];
+
+/// Value of each of `SUMMARY_METRICS` for a perfect tile. The worst tile is
+/// the one furthest from it.
+const SUMMARY_IDEALS: [f64; 10] = [0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+
+/// Distribution of a metric over tiles, and over observations for the night
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MetricSummary {
+    pub(crate) median: f64,
+
+    pub(crate) p90: f64,
+
+    // Observation with the worst tile, only set for the night summary
+    pub(crate) worst_obsid: Option<usize>,
+
+    // Tile furthest from the metric's ideal value
+    pub(crate) worst_tile: Option<usize>,
+
+    pub(crate) worst: f64,
+}
+
+/// Summary of every observation and of the whole night
+#[derive(Debug)]
+pub(crate) struct Summary {
+    pub(crate) obsids: Vec<usize>,
+
+    // Summary of each metric in `SUMMARY_METRICS`, indexed [obs, metric]
+    pub(crate) per_obs: Vec<Vec<MetricSummary>>,
+
+    // Summary of each metric over all tiles of all observations
+    pub(crate) night: Vec<MetricSummary>,
+
+    // Number of fully flagged tiles in each observation, NaN if unknown
+    pub(crate) num_flagged_tiles: Vec<f64>,
+
+    // Flagged fraction of each observation, NaN if unknown
+    pub(crate) obs_flag_occupancy: Vec<f64>,
+}
+
+/// Summarise the per-tile `metrics`, in the order of `SUMMARY_METRICS`, over
+/// the union of observations that any of them cover
+pub(crate) fn summarise(
+    metrics: &[TileValues],
+    num_flagged_tiles: &ObsValues,
+    obs_flag_occupancy: &ObsValues,
+) -> Summary {
+    let obsids: Vec<usize> = metrics
+        .iter()
+        .flat_map(|(obsids, _)| obsids)
+        .chain(&num_flagged_tiles.0)
+        .chain(&obs_flag_occupancy.0)
+        .copied()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+
+    let per_obs = obsids
+        .iter()
+        .map(|obsid| {
+            metrics
+                .iter()
+                .zip(SUMMARY_IDEALS)
+                .map(|((metric_obsids, values), ideal)| {
+                    let tile_values = metric_obsids
+                        .iter()
+                        .position(|id| id == obsid)
+                        .map_or(&[][..], |i| &values[i][..]);
+                    summarise_values(
+                        tile_values.iter().enumerate().map(|(t, &v)| (None, t, v)),
+                        ideal,
+                    )
+                })
+                .collect()
+        })
+        .collect();
+
+    let night = metrics
+        .iter()
+        .zip(SUMMARY_IDEALS)
+        .map(|((metric_obsids, values), ideal)| {
+            summarise_values(
+                metric_obsids
+                    .iter()
+                    .zip(values)
+                    .flat_map(|(&obsid, tiles)| {
+                        tiles
+                            .iter()
+                            .enumerate()
+                            .map(move |(t, &v)| (Some(obsid), t, v))
+                    }),
+                ideal,
+            )
+        })
+        .collect();
+
+    let per_obs_value = |(value_obsids, values): &ObsValues| -> Vec<f64> {
+        obsids
+            .iter()
+            .map(|obsid| {
+                value_obsids
+                    .iter()
+                    .position(|id| id == obsid)
+                    .map_or(f64::NAN, |i| values[i])
+            })
+            .collect()
+    };
+
+    Summary {
+        num_flagged_tiles: per_obs_value(num_flagged_tiles),
+        obs_flag_occupancy: per_obs_value(obs_flag_occupancy),
+        obsids,
+        per_obs,
+        night,
+    }
+}
+
+/// Median, 90th percentile and worst of (obsid, tile, value) triples,
+/// ignoring NaNs. The worst value is the one furthest from `ideal`.
+fn summarise_values(
+    values: impl Iterator<Item = (Option<usize>, usize, f64)>,
+    ideal: f64,
+) -> MetricSummary {
+    let mut valid: Vec<(Option<usize>, usize, f64)> =
+        values.filter(|(_, _, v)| !v.is_nan()).collect();
+    valid.sort_by(|a, b| a.2.total_cmp(&b.2));
+
+    let sorted: Vec<f64> = valid.iter().map(|&(_, _, v)| v).collect();
+    let (worst_obsid, worst_tile, worst) = valid
+        .iter()
+        .max_by(|a, b| (a.2 - ideal).abs().total_cmp(&(b.2 - ideal).abs()))
+        .map_or((None, None, f64::NAN), |&(obsid, tile, v)| {
+            (obsid, Some(tile), v)
+        });
+
+    MetricSummary {
+        median: quantile(&sorted, 0.5),
+        p90: quantile(&sorted, 0.9),
+        worst_obsid,
+        worst_tile,
+        worst,
+    }
+}
+
+/// Linearly interpolated quantile of sorted values, NaN if there are none
+fn quantile(sorted: &[f64], q: f64) -> f64 {
+    if sorted.is_empty() {
+        return f64::NAN;
+    }
+    let pos = q * (sorted.len() - 1) as f64;
+    let below = pos.floor() as usize;
+    let above = pos.ceil() as usize;
+    sorted[below] + (sorted[above] - sorted[below]) * (pos - below as f64)
+}
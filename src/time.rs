@@ -0,0 +1,221 @@
+/// Longitude of the MWA [deg east]
+pub(crate) const MWA_LONGITUDE: f64 = 116.67081524;
+
+/// Unix time of the GPS epoch, 1980-01-06 00:00:00 UTC
+const GPS_EPOCH_UNIX: i64 = 315_964_800;
+
+/// Modified Julian date of the Unix epoch
+const UNIX_EPOCH_MJD: f64 = 40_587.0;
+
+/// Unix times at which each leap second since the GPS epoch took effect. The
+/// GPS-UTC offset is the number of entries at or before a given time.
+const LEAP_SECONDS_UNIX: [i64; 18] = [
+    362_793_600,   // 1981-07-01
+    394_329_600,   // 1982-07-01
+    425_865_600,   // 1983-07-01
+    489_024_000,   // 1985-07-01
+    567_993_600,   // 1988-01-01
+    631_152_000,   // 1990-01-01
+    662_688_000,   // 1991-01-01
+    709_948_800,   // 1992-07-01
+    741_484_800,   // 1993-07-01
+    773_020_800,   // 1994-07-01
+    820_454_400,   // 1996-01-01
+    867_715_200,   // 1997-07-01
+    915_148_800,   // 1999-01-01
+    1_136_073_600, // 2006-01-01
+    1_230_768_000, // 2009-01-01
+    1_341_100_800, // 2012-07-01
+    1_435_708_800, // 2015-07-01
+    1_483_228_800, // 2017-01-01
+];
+
+/// Column names matching `obsid_columns`
+pub(crate) const OBSID_COLUMNS: &str = "obsid utc mjd lst[deg]";
+
+/// Time of an observation in the forms needed for plotting
+#[derive(Debug)]
+pub(crate) struct ObsTime {
+    // ISO 8601 UTC, e.g. 2014-07-21T20:10:24
+    pub(crate) utc: String,
+
+    // Modified Julian date (UTC)
+    pub(crate) mjd: f64,
+
+    // Local mean sidereal time [deg]
+    pub(crate) lst: f64,
+}
+
+impl ObsTime {
+    /// Convert GPS seconds, i.e. an MWA obsid, accounting for leap seconds.
+    /// The LST is that of an array at `longitude` [deg east].
+    pub(crate) fn from_gps(gps: usize, longitude: f64) -> Self {
+        let gps_unix = gps as i64 + GPS_EPOCH_UNIX;
+        let leap_seconds = LEAP_SECONDS_UNIX
+            .iter()
+            .enumerate()
+            .filter(|&(num_before, &leap)| gps_unix - num_before as i64 > leap)
+            .count() as i64;
+        let unix = gps_unix - leap_seconds;
+
+        // A leap second is written as the 61st second of the minute before
+        let is_leap_second = LEAP_SECONDS_UNIX
+            .iter()
+            .enumerate()
+            .any(|(num_before, &leap)| gps_unix - num_before as i64 == leap);
+        let shown = unix - i64::from(is_leap_second);
+
+        let days = shown.div_euclid(86_400);
+        let seconds = shown.rem_euclid(86_400);
+        let (year, month, day) = civil_from_days(days);
+        let utc = format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            year,
+            month,
+            day,
+            seconds / 3600,
+            seconds % 3600 / 60,
+            seconds % 60 + i64::from(is_leap_second)
+        );
+
+        let mjd = unix as f64 / 86_400.0 + UNIX_EPOCH_MJD;
+        let lst = (gmst(mjd) + longitude).rem_euclid(360.0);
+
+        Self { utc, mjd, lst }
+    }
+}
+
+/// GPS seconds of an ISO 8601 UTC date such as the DATE-OBS of a FITS file,
+/// e.g. 2014-07-21T20:10:24.5, rounded to the nearest second
+pub(crate) fn gps_from_utc(date: &str) -> Option<usize> {
+    let (date, time) = date
+        .trim()
+        .split_once('T')
+        .unwrap_or((date.trim(), "00:00:00"));
+
+    let mut date_parts = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let year = date_parts.next()??;
+    let month = date_parts.next()??;
+    let day = date_parts.next()??;
+
+    let mut time_parts = time.splitn(3, ':').map(|p| p.parse::<f64>().ok());
+    let hours = time_parts.next()??;
+    let minutes = time_parts.next().flatten().unwrap_or(0.0);
+    let seconds = time_parts.next().flatten().unwrap_or(0.0);
+
+    let day_seconds = (hours * 3600.0 + minutes * 60.0 + seconds).round() as i64;
+    let unix = days_from_civil(year, month, day) * 86_400 + day_seconds;
+    // A leap second, e.g. 23:59:60, comes before the offset it adds
+    let leap_seconds = LEAP_SECONDS_UNIX
+        .iter()
+        .filter(|&&leap| unix - i64::from(seconds >= 60.0) >= leap)
+        .count() as i64;
+
+    usize::try_from(unix - GPS_EPOCH_UNIX + leap_seconds).ok()
+}
+
+/// Obsid followed by its UTC, MJD and LST at `longitude` [deg east], as
+/// written in results files
+pub(crate) fn obsid_columns(obsid: usize, longitude: f64) -> String {
+    let time = ObsTime::from_gps(obsid, longitude);
+    format!("{} {} {:.8} {:.6}", obsid, time.utc, time.mjd, time.lst)
+}
+
+/// Greenwich mean sidereal time [deg] of a UTC MJD, treating UTC as UT1
+fn gmst(mjd: f64) -> f64 {
+    // Days and Julian centuries since J2000.0
+    let d = mjd - 51_544.5;
+    let t = d / 36_525.0;
+    280.460_618_37 + 360.985_647_366_29 * d + 0.000_387_933 * t * t - t * t * t / 38_710_000.0
+}
+
+/// Year, month and day of a number of days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    // Shift to an era starting on 0000-03-01, so leap days fall at the end
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Number of days since 1970-01-01 of a year, month and day
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utc_of_known_obsids() {
+        assert_eq!(
+            ObsTime::from_gps(0, MWA_LONGITUDE).utc,
+            "1980-01-06T00:00:00"
+        );
+        assert_eq!(
+            ObsTime::from_gps(1_090_008_640, MWA_LONGITUDE).utc,
+            "2014-07-21T20:10:24"
+        );
+        assert_eq!(
+            ObsTime::from_gps(1_400_000_000, MWA_LONGITUDE).utc,
+            "2024-05-17T16:53:02"
+        );
+        assert_eq!(
+            ObsTime::from_gps(914_803_214, MWA_LONGITUDE).utc,
+            "2008-12-31T23:59:60"
+        );
+        assert!(
+            (ObsTime::from_gps(1_090_008_640, MWA_LONGITUDE).mjd - 56_859.840_555_56).abs() < 1e-8
+        );
+    }
+
+    #[test]
+    fn lst_follows_longitude() {
+        let greenwich = ObsTime::from_gps(1_090_008_640, 0.0).lst;
+        let mwa = ObsTime::from_gps(1_090_008_640, MWA_LONGITUDE).lst;
+        assert!(((mwa - greenwich).rem_euclid(360.0) - MWA_LONGITUDE).abs() < 1e-9);
+    }
+
+    #[test]
+    fn gps_of_known_dates() {
+        assert_eq!(gps_from_utc("1980-01-06T00:00:00"), Some(0));
+        assert_eq!(gps_from_utc("2014-07-21T20:10:24"), Some(1_090_008_640));
+        assert_eq!(gps_from_utc("2014-07-21T20:10:23.6"), Some(1_090_008_640));
+        assert_eq!(gps_from_utc(" 2024-05-17T16:53:02 "), Some(1_400_000_000));
+        assert_eq!(gps_from_utc("2014-07-21"), Some(1_089_936_016));
+        assert_eq!(gps_from_utc("1979-12-31T00:00:00"), None);
+        assert_eq!(gps_from_utc("not a date"), None);
+    }
+
+    #[test]
+    fn gps_of_leap_seconds() {
+        // The leap second itself and the seconds either side of it
+        assert_eq!(gps_from_utc("2008-12-31T23:59:59"), Some(914_803_213));
+        assert_eq!(gps_from_utc("2008-12-31T23:59:60"), Some(914_803_214));
+        assert_eq!(gps_from_utc("2009-01-01T00:00:00"), Some(914_803_215));
+        assert_eq!(gps_from_utc("2016-12-31T23:59:60"), Some(1_167_264_017));
+        assert_eq!(gps_from_utc("2017-01-01T00:00:00"), Some(1_167_264_018));
+    }
+
+    #[test]
+    fn gps_round_trip_across_leap_seconds() {
+        // Either side of the leap seconds at the end of 2008 and 2016
+        for gps in (914_803_000..914_804_000).chain(1_167_263_500..1_167_264_500) {
+            let utc = ObsTime::from_gps(gps, MWA_LONGITUDE).utc;
+            assert_eq!(gps_from_utc(&utc), Some(gps), "{}", utc);
+        }
+    }
+}